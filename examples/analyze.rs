@@ -0,0 +1,92 @@
+use clap::Parser;
+use indicatif::ProgressIterator;
+use reichtum::agent::create_agent;
+use reichtum::game_state::GameState;
+use reichtum::stats::GameStats;
+use std::collections::BTreeMap;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(short, long, default_value_t = 1000)]
+    games: usize,
+    #[clap(short, long, value_delimiter = ',', default_value = "1,2")]
+    agents: Vec<usize>,
+    // Seed for the first game; game i uses seed + i.
+    #[clap(short, long, default_value_t = 0)]
+    seed: u64,
+}
+
+fn main() {
+    let args = Args::parse();
+    let num_players = args.agents.len();
+    let players = args
+        .agents
+        .iter()
+        .map(|lvl| create_agent(*lvl))
+        .collect::<Vec<_>>();
+    let mut rounds = Vec::new();
+    let mut margins = Vec::new();
+    let mut wins = vec![0; num_players];
+    let mut stats = GameStats::new(num_players);
+    for i in (0..args.games).progress() {
+        let seed = args.seed + i as u64;
+        let mut gs = GameState::init_seeded(num_players, seed).expect("Failed to init game");
+        let mut game_stats = GameStats::new(num_players);
+        for _turn in 1..=1000 {
+            let idx = gs.curr_player_idx;
+            let action = players[idx].choose_action(&gs);
+            let is_over = gs.take_turn(&action).expect("Agent logic error").is_over;
+            game_stats.record(idx, &action);
+            if is_over {
+                break;
+            }
+        }
+        stats.merge(&game_stats);
+        rounds.push(gs.round() as usize);
+        let mut scores = gs.players.iter().map(|p| p.vp()).collect::<Vec<_>>();
+        scores.sort_unstable_by(|a, b| b.cmp(a));
+        margins.push((scores[0] - scores[1]) as usize);
+        if let Some(w) = gs.winner() {
+            wins[w] += 1;
+        }
+    }
+
+    print_histogram("Game length (rounds)", &rounds);
+    print_histogram("Win margin (VP)", &margins);
+    println!("\nWins:");
+    for (i, w) in wins.iter().enumerate() {
+        println!(
+            "  {}(d={}): {}",
+            (i as u8 + b'A') as char,
+            args.agents[i],
+            w
+        );
+    }
+    println!("\nAction counts per game:");
+    println!("  player  take3  take2same  fewer  buys  reserves");
+    for (i, s) in stats.players.iter().enumerate() {
+        let per_game = |n: u32| n as f64 / args.games as f64;
+        println!(
+            "  {:>6}  {:>5.1}  {:>9.1}  {:>5.1}  {:>4.1}  {:>8.1}",
+            (i as u8 + b'A') as char,
+            per_game(s.take_three),
+            per_game(s.take_two_same),
+            per_game(s.take_fewer),
+            per_game(s.buys),
+            per_game(s.reserves),
+        );
+    }
+}
+
+fn print_histogram(title: &str, values: &[usize]) {
+    let mut counts = BTreeMap::new();
+    for v in values {
+        *counts.entry(*v).or_insert(0) += 1;
+    }
+    let max_count = counts.values().copied().max().unwrap_or(1);
+    println!("\n{}:", title);
+    for (value, count) in counts.iter() {
+        let width = count * 50 / max_count;
+        println!("  {:>4} | {:<50} {}", value, "#".repeat(width), count);
+    }
+}
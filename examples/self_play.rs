@@ -1,8 +1,13 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use indicatif::ProgressIterator;
 use polars::prelude::*;
 use reichtum::agent::create_agent;
 use reichtum::game_state::GameState;
+use reichtum::log::GameLogger;
+use reichtum::stats::{GameRecord, Summary, WinTally};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 struct Args {
@@ -12,16 +17,44 @@ struct Args {
     agents: Vec<usize>,
     #[clap(short, long, default_value_t = false)]
     verbose: bool,
+    #[clap(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    // Stop early once every agent's 95% win-rate interval is narrower than this.
+    #[clap(long)]
+    converge: Option<f64>,
+    // Write every move to this file as JSON Lines, for training.
+    #[clap(long)]
+    log: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Csv,
+    Json,
 }
 
 fn main() {
     let args = Args::parse();
-    let mut scores = run_games(args.games, &args.agents);
+    let mut logger = args.log.as_ref().map(|path| {
+        let file = File::create(path).expect("Failed to create log file");
+        GameLogger::new(BufWriter::new(file))
+    });
+    let records = run_games(args.games, &args.agents, args.converge, logger.as_mut());
+    if args.format == Format::Json {
+        for record in records.iter() {
+            println!("{}", serde_json::to_string(record).unwrap());
+        }
+        return;
+    }
+    let summary = Summary::new(&records, &agent_names(&args.agents));
+    let mut scores = scores_frame(&records, &args.agents);
     if args.verbose {
         CsvWriter::new(&mut std::io::stdout())
             .has_header(true)
             .finish(&mut scores)
             .unwrap();
+        // On stderr, so stdout stays valid CSV.
+        eprint!("{}", summary);
         return;
     }
     println!("Scores: {}", &scores.describe(None));
@@ -45,6 +78,7 @@ fn main() {
         .collect()
         .unwrap();
     println!("Rankings (1=winner): {}", &rankings.describe(None));
+    print!("{}", summary);
 
     // TODO:
     //  - Show score box plots for each player
@@ -52,27 +86,36 @@ fn main() {
     //  - Compute running Elo ratings for each player and plot them
 }
 
-fn run_games(num_games: usize, agents: &[usize]) -> DataFrame {
+// How often (in games) to check for win-rate convergence.
+const CONVERGENCE_CHECK_INTERVAL: usize = 50;
+
+fn run_games(
+    num_games: usize,
+    agents: &[usize],
+    converge: Option<f64>,
+    mut logger: Option<&mut GameLogger<BufWriter<File>>>,
+) -> Vec<GameRecord> {
     let num_players = agents.len();
     let players = agents
         .iter()
         .map(|lvl| create_agent(*lvl))
         .collect::<Vec<_>>();
-    let names = agents
-        .iter()
-        .enumerate()
-        .map(|(i, lvl)| format!("{}(d={})", (i as u8 + b'A') as char, lvl))
-        .collect::<Vec<_>>();
-    let mut scores = (0..num_players)
-        .map(|_| Vec::<i32>::new())
-        .collect::<Vec<_>>();
-    for _ in (0..num_games).progress() {
-        let mut gs = GameState::init(num_players).expect("Failed to initialize game state");
+    let names = agent_names(agents);
+    let mut records = Vec::with_capacity(num_games);
+    let mut tally = WinTally::new(num_players);
+    for i in (0..num_games).progress() {
+        let seed = rand::random();
+        let mut gs =
+            GameState::init_seeded(num_players, seed).expect("Failed to initialize game state");
         for _turn in 1..=1000 {
             let action = players[gs.curr_player_idx].choose_action(&gs);
-            match gs.take_turn(&action) {
-                Ok(true) => break,
-                Ok(false) => (),
+            let result = match logger.as_deref_mut() {
+                Some(logger) => logger.play(&mut gs, &action),
+                None => gs.take_turn(&action),
+            };
+            match result {
+                Ok(outcome) if outcome.is_over => break,
+                Ok(_) => (),
                 Err(e) => {
                     println!(
                         "{:?} for agent {} action: {:?}",
@@ -83,14 +126,42 @@ fn run_games(num_games: usize, agents: &[usize]) -> DataFrame {
                 }
             };
         }
-        gs.players.iter().enumerate().for_each(|(i, p)| {
-            scores[i].push(p.vp() as i32);
+        records.push(GameRecord {
+            seed,
+            scores: gs.players.iter().map(|p| p.vp()).collect(),
+            rounds: gs.round(),
+            winner: gs.winner(),
         });
+        tally.record(gs.winner());
+        if let Some(width) = converge {
+            if (i + 1) % CONVERGENCE_CHECK_INTERVAL == 0 && tally.has_converged(width) {
+                eprintln!("Win rates converged after {} games", i + 1);
+                break;
+            }
+        }
     }
-    let columns = names
+    records
+}
+
+fn agent_names(agents: &[usize]) -> Vec<String> {
+    agents
+        .iter()
+        .enumerate()
+        .map(|(i, lvl)| format!("{}(d={})", (i as u8 + b'A') as char, lvl))
+        .collect()
+}
+
+fn scores_frame(records: &[GameRecord], agents: &[usize]) -> DataFrame {
+    let columns = agent_names(agents)
         .iter()
         .enumerate()
-        .map(|(i, name)| Series::new(name, &scores[i]))
+        .map(|(i, name)| {
+            let scores = records
+                .iter()
+                .map(|r| r.scores[i] as i32)
+                .collect::<Vec<_>>();
+            Series::new(name, scores)
+        })
         .collect::<Vec<_>>();
     DataFrame::new(columns).unwrap()
 }
@@ -0,0 +1,186 @@
+use reichtum::agent::{create_agent, Agent, TimeLimited};
+use reichtum::data_types::{Action, Audience, TurnOutcome};
+use reichtum::game_state::GameState;
+use std::collections::HashMap;
+use std::time::Duration;
+use tiny_http::{Method, Request, Response, Server};
+
+// A single in-memory game, with an optional agent per seat.
+// Seats without an agent are controlled by HTTP clients.
+struct Game {
+    state: GameState,
+    agents: Vec<Option<Box<dyn Agent + Send>>>,
+    // The state before each human action, for undo.
+    history: Vec<GameState>,
+}
+
+// How long an agent may think about each move before it must play.
+const AGENT_BUDGET: Duration = Duration::from_secs(2);
+
+type HttpResult = Result<String, (u16, String)>;
+
+fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8000".to_string());
+    let server = Server::http(&addr).expect("Failed to start server");
+    println!("Listening on http://{}", addr);
+    // Endpoints:
+    //   POST /game?humans=1&ai=1&difficulty=1  -> create a new game
+    //   POST /action?player=0  (body: Action JSON)  -> play a turn
+    //   POST /undo  -> take back the last human action, and the agents' replies
+    //   GET  /actions?player=0  -> list that player's legal actions, as JSON
+    //   GET  /view[?player=0]  -> fetch the game state, as seen by that player
+    //                             (or by a spectator, if none is given)
+    let mut game: Option<Game> = None;
+    for mut request in server.incoming_requests() {
+        let result = handle(&mut request, &mut game);
+        let (status, body) = match result {
+            Ok(body) => (200, body),
+            Err((status, msg)) => (status, format!("{{\"error\":{:?}}}", msg)),
+        };
+        let response = Response::from_string(body).with_status_code(status);
+        if let Err(e) = request.respond(response) {
+            println!("Failed to respond: {}", e);
+        }
+    }
+}
+
+fn handle(request: &mut Request, game: &mut Option<Game>) -> HttpResult {
+    let (path, query) = parse_url(request.url());
+    match (request.method(), path.as_str()) {
+        (Method::Post, "/game") => {
+            let humans = query_param(&query, "humans", Some(1))?;
+            let ai = query_param(&query, "ai", Some(1))?;
+            let difficulty = query_param(&query, "difficulty", Some(1))?;
+            let state = GameState::init(humans + ai).map_err(bad_request)?;
+            let agents = (0..humans)
+                .map(|_| None)
+                .chain((0..ai).map(|_| {
+                    let agent = TimeLimited::new(create_agent(difficulty), AGENT_BUDGET);
+                    Some(Box::new(agent) as Box<dyn Agent + Send>)
+                }))
+                .collect();
+            let g = game.insert(Game {
+                state,
+                agents,
+                history: Vec::new(),
+            });
+            advance_agents(g)?;
+            view(g, Audience::Spectator)
+        }
+        (Method::Post, "/action") => {
+            let g = game
+                .as_mut()
+                .ok_or((404, "No game in progress".to_string()))?;
+            let player = query_param(&query, "player", None)?;
+            check_turn(g, player)?;
+            let mut body = String::new();
+            request
+                .as_reader()
+                .read_to_string(&mut body)
+                .map_err(bad_request)?;
+            let action: Action = serde_json::from_str(&body).map_err(bad_request)?;
+            let before = g.state.clone();
+            let outcome = g.state.take_turn(&action).map_err(bad_request)?;
+            g.history.push(before);
+            notify(player, &action, &outcome);
+            advance_agents(g)?;
+            view(g, Audience::Player(player))
+        }
+        (Method::Post, "/undo") => {
+            let g = game
+                .as_mut()
+                .ok_or((404, "No game in progress".to_string()))?;
+            if g.state.is_finished() {
+                return Err((409, "Game is over".to_string()));
+            }
+            g.state = g
+                .history
+                .pop()
+                .ok_or((409, "Nothing to undo".to_string()))?;
+            let player = g.state.curr_player_idx;
+            view(g, Audience::Player(player))
+        }
+        (Method::Get, "/actions") => {
+            let g = game.as_ref().ok_or((404, "No game".to_string()))?;
+            let player = query_param(&query, "player", None)?;
+            check_turn(g, player)?;
+            serde_json::to_string(&g.state.valid_actions()).map_err(|e| (500, e.to_string()))
+        }
+        (Method::Get, "/view") => {
+            let g = game.as_ref().ok_or((404, "No game".to_string()))?;
+            let audience = match query.get("player") {
+                Some(_) => Audience::Player(query_param(&query, "player", None)?),
+                None => Audience::Spectator,
+            };
+            view(g, audience)
+        }
+        _ => Err((404, format!("Unknown endpoint: {}", path))),
+    }
+}
+
+// Only the human whose turn it is may act.
+fn check_turn(game: &Game, player: usize) -> Result<(), (u16, String)> {
+    if game.state.is_finished() {
+        return Err((409, "Game is over".to_string()));
+    }
+    if player != game.state.curr_player_idx || game.agents[player].is_some() {
+        return Err((409, "Not your turn".to_string()));
+    }
+    Ok(())
+}
+
+fn advance_agents(game: &mut Game) -> Result<(), (u16, String)> {
+    game.state
+        .fast_forward(&game.agents, &mut notify)
+        .map_err(|e| (500, e.to_string()))?;
+    Ok(())
+}
+
+// Stand-in for pushing notices to connected clients.
+fn notify(player: usize, action: &Action, outcome: &TurnOutcome) {
+    println!(
+        "player {} played {:?} (game over: {})",
+        player, action, outcome.is_over
+    );
+    if let Some(card) = &outcome.card {
+        println!("player {} took card #{}: {}", player, card.id, card);
+    }
+    if let Some(noble) = &outcome.noble {
+        println!("player {} acquired {}", player, noble);
+    }
+}
+
+fn view(game: &Game, audience: Audience) -> HttpResult {
+    game.state
+        .serialize_for(audience)
+        .map_err(|e| (400, e.to_string()))
+}
+
+fn bad_request(e: impl ToString) -> (u16, String) {
+    (400, e.to_string())
+}
+
+fn parse_url(url: &str) -> (String, HashMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    (path.to_string(), params)
+}
+
+fn query_param(
+    query: &HashMap<String, String>,
+    key: &str,
+    default: Option<usize>,
+) -> Result<usize, (u16, String)> {
+    match query.get(key) {
+        Some(v) => v
+            .parse()
+            .map_err(|_| (400, format!("Invalid value for {}: {}", key, v))),
+        None => default.ok_or_else(|| (400, format!("Missing parameter: {}", key))),
+    }
+}
@@ -0,0 +1,95 @@
+use clap::{Parser, Subcommand};
+use reichtum::agent::create_agent;
+use reichtum::data_types::Action;
+use reichtum::game_state::GameState;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // Play a seeded self-play game and write it to a file.
+    Record {
+        path: String,
+        #[clap(short, long, default_value_t = 0)]
+        seed: u64,
+        #[clap(short, long, value_delimiter = ',', default_value = "1,2")]
+        agents: Vec<usize>,
+    },
+    // Replay a recorded game and check that the final scores match.
+    Verify {
+        path: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedGame {
+    seed: u64,
+    agents: Vec<usize>,
+    actions: Vec<(usize, Action)>,
+    scores: Vec<u8>,
+}
+
+fn main() {
+    let args = Args::parse();
+    match args.command {
+        Command::Record { path, seed, agents } => {
+            let game = record_game(seed, &agents);
+            let file = File::create(&path).expect("Failed to create file");
+            serde_json::to_writer(file, &game).expect("Failed to write game");
+            println!("Recorded {} actions to {}", game.actions.len(), path);
+        }
+        Command::Verify { path } => {
+            let file = File::open(&path).expect("Failed to open file");
+            let game: RecordedGame = serde_json::from_reader(file).expect("Failed to parse game");
+            if let Err(msg) = verify_game(&game) {
+                println!("Replay diverged: {}", msg);
+                std::process::exit(1);
+            }
+            println!(
+                "Replay OK: {} actions, scores {:?}",
+                game.actions.len(),
+                game.scores
+            );
+        }
+    }
+}
+
+fn record_game(seed: u64, agents: &[usize]) -> RecordedGame {
+    let players = agents
+        .iter()
+        .map(|lvl| create_agent(*lvl))
+        .collect::<Vec<_>>();
+    let mut gs = GameState::init_seeded(agents.len(), seed).expect("Failed to initialize game");
+    for _turn in 1..=1000 {
+        let idx = gs.curr_player_idx;
+        let action = players[idx].choose_action(&gs);
+        if gs.take_turn(&action).expect("Agent logic error").is_over {
+            break;
+        }
+    }
+    RecordedGame {
+        seed,
+        agents: agents.to_vec(),
+        actions: gs.history().to_vec(),
+        scores: gs.players.iter().map(|p| p.vp()).collect(),
+    }
+}
+
+fn verify_game(game: &RecordedGame) -> Result<(), String> {
+    let gs = GameState::replay(game.agents.len(), game.seed, &game.actions)
+        .map_err(|e| e.to_string())?;
+    let scores = gs.players.iter().map(|p| p.vp()).collect::<Vec<_>>();
+    if scores != game.scores {
+        return Err(format!(
+            "final scores {:?} do not match recorded {:?}",
+            scores, game.scores
+        ));
+    }
+    Ok(())
+}
@@ -1,34 +1,148 @@
-use crate::data_types::{Action, CardLocation};
+use crate::data_types::{Action, CardLocation, Color};
 use crate::game_state::GameState;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
 
 pub fn create_agent(difficulty: usize) -> Box<dyn Agent + Send> {
     match difficulty {
         // Completely random actions.
         0 => Box::<RandomAgent>::default(),
         // Only cares about VP.
-        1 => Box::new(GreedyAgent {
-            bonuses: ScoringBonuses {
-                vp: 100,
-                card_needed: 0,
-                color_needed: 0,
-                reserve_discount: 10,
-            },
+        1 => Box::new(GreedyAgent::new(ScoringBonuses::GREEDY)),
+        // Looks two of its own moves ahead.
+        3 => Box::new(ExpectimaxAgent::new(2)),
+        // Searches its move and the opponent's reply, in 2-player games.
+        4 => Box::new(MinimaxAgent::new(2)),
+        // Balanced, but grabs VP when an opponent is about to win.
+        5 => Box::new(EndgameAgent {
+            greedy: GreedyAgent::new(ScoringBonuses::BALANCED),
         }),
-        // Balances raw VP, nobles, and card purchasing power.
-        _ => Box::new(GreedyAgent {
-            bonuses: ScoringBonuses {
-                vp: 1000,
-                card_needed: 10,
-                color_needed: 1,
-                reserve_discount: 10,
-            },
+        // Baseline: balanced scoring, but never reserves a card.
+        10 => Box::new(NoReserveAgent {
+            greedy: GreedyAgent::new(ScoringBonuses::BALANCED),
         }),
+        // Casual opponent: balanced, but blunders 30% of the time.
+        11 => Box::new(NoisyAgent::new(
+            GreedyAgent::new(ScoringBonuses::BALANCED),
+            0.7,
+        )),
+        // Between random and greedy: epsilon-greedy with epsilon 0.5. Use
+        // create_epsilon_greedy_agent for other values of epsilon.
+        12 => create_epsilon_greedy_agent(0.5),
+        // Balances raw VP, nobles, and card purchasing power.
+        _ => Box::new(GreedyAgent::new(ScoringBonuses::BALANCED)),
     }
 }
 
+// Readable names for the difficulty levels, for configs and CLIs.
+pub const AGENT_NAMES: &[(&str, usize)] = &[
+    ("random", 0),
+    ("greedy", 1),
+    ("balanced", 2),
+    ("expectimax", 3),
+    ("minimax", 4),
+    ("endgame", 5),
+    ("no-reserve", 10),
+    ("noisy", 11),
+    ("epsilon-greedy", 12),
+];
+
+// Plays a random legal move with probability `epsilon`, and level 1's move
+// otherwise.
+pub fn create_epsilon_greedy_agent(epsilon: f64) -> Box<dyn Agent + Send> {
+    Box::new(epsilon_greedy(epsilon, rand::random()))
+}
+fn epsilon_greedy(epsilon: f64, seed: u64) -> NoisyAgent<GreedyAgent> {
+    NoisyAgent::seeded(
+        GreedyAgent::new(ScoringBonuses::GREEDY),
+        1.0 - epsilon,
+        seed,
+    )
+}
+
+pub fn create_agent_by_name(name: &str) -> Option<Box<dyn Agent + Send>> {
+    AGENT_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, difficulty)| create_agent(*difficulty))
+}
+
+// Receives (player index, candidate action, score) for every action an agent
+// considers, for auditing agent decisions.
+pub type ThinkLog = Box<dyn FnMut(usize, &Action, i32) + Send>;
+
 pub trait Agent {
     fn choose_action(&self, game: &GameState) -> Action;
+    // Agents that score their candidate actions report them to this sink.
+    // By default, nothing is logged.
+    fn set_think_log(&mut self, _sink: ThinkLog) {}
+    // Like choose_action, but returns by `deadline`. Searching agents stop
+    // early and play their best move so far; the rest ignore it.
+    fn choose_action_within(&self, game: &GameState, _deadline: Instant) -> Action {
+        self.choose_action(game)
+    }
+}
+
+impl<A: Agent + ?Sized> Agent for Box<A> {
+    fn choose_action(&self, game: &GameState) -> Action {
+        (**self).choose_action(game)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        (**self).set_think_log(sink);
+    }
+    fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+        (**self).choose_action_within(game, deadline)
+    }
+}
+
+// Gives the wrapped agent `budget` to pick each move. The deadline is
+// cooperative: agents check it between nodes rather than being interrupted.
+pub struct TimeLimited<A: Agent> {
+    inner: A,
+    budget: Duration,
+}
+impl<A: Agent> TimeLimited<A> {
+    pub fn new(inner: A, budget: Duration) -> Self {
+        Self { inner, budget }
+    }
+}
+impl<A: Agent> Agent for TimeLimited<A> {
+    fn choose_action(&self, game: &GameState) -> Action {
+        self.inner
+            .choose_action_within(game, Instant::now() + self.budget)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.inner.set_think_log(sink);
+    }
+    fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+        let own = Instant::now() + self.budget;
+        self.inner.choose_action_within(game, deadline.min(own))
+    }
+}
+
+fn past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() >= d)
+}
+
+// Iterative deepening: runs `search` at depths 1 to `max_depth` and keeps the
+// result of the deepest one that finished before the deadline. The depth-1
+// result is always kept, so there's a move even if time already ran out.
+fn deepen(max_depth: usize, deadline: Instant, search: impl Fn(usize) -> Action) -> Action {
+    let mut best = search(1);
+    for depth in 2..=max_depth {
+        if Instant::now() >= deadline {
+            break;
+        }
+        let action = search(depth);
+        if Instant::now() >= deadline {
+            break;
+        }
+        best = action;
+    }
+    best
 }
 
 #[derive(Default)]
@@ -47,10 +161,38 @@ impl Agent for RandomAgent {
 
 pub struct GreedyAgent {
     bonuses: ScoringBonuses,
+    think_log: Option<RefCell<ThinkLog>>,
 }
 impl Agent for GreedyAgent {
     fn choose_action(&self, game: &GameState) -> Action {
-        let actions = game.valid_actions();
+        self.choose_from(game, game.valid_actions())
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.think_log = Some(RefCell::new(sink));
+    }
+}
+impl GreedyAgent {
+    fn new(bonuses: ScoringBonuses) -> Self {
+        Self {
+            bonuses,
+            think_log: None,
+        }
+    }
+    fn choose_from(&self, game: &GameState, mut actions: Vec<Action>) -> Action {
+        // Hard rules, applied before any heuristic scoring:
+        //  - Always take a buy that reaches the VP target.
+        //  - Never take tokens at the token cap if a VP-scoring buy exists.
+        if let Some(action) = winning_buy(game, &actions) {
+            return action;
+        }
+        let scoring_buy = |a: &Action| match a {
+            Action::BuyCard(loc) => game.peek_card(loc).is_ok_and(|c| c.vp > 0),
+            _ => false,
+        };
+        let at_cap = game.curr_player().num_tokens() >= game.ruleset().max_tokens;
+        if at_cap && actions.iter().any(scoring_buy) {
+            actions.retain(|a| matches!(a, Action::BuyCard(_)));
+        }
         if actions.len() == 1 {
             return actions[0].clone();
         }
@@ -59,6 +201,12 @@ impl Agent for GreedyAgent {
             .iter()
             .map(|a| (a, info.score_action(game, a, &self.bonuses)))
             .collect::<Vec<_>>();
+        if let Some(log) = &self.think_log {
+            let mut log = log.borrow_mut();
+            for (a, score) in scored_actions.iter() {
+                log(game.curr_player_idx, a, *score);
+            }
+        }
         let best_score = scored_actions.iter().map(|(_, s)| s).max().unwrap();
         let best_actions: Vec<&Action> = scored_actions
             .iter()
@@ -71,11 +219,377 @@ impl Agent for GreedyAgent {
     }
 }
 
+pub struct NoReserveAgent {
+    greedy: GreedyAgent,
+}
+impl Agent for NoReserveAgent {
+    fn choose_action(&self, game: &GameState) -> Action {
+        let mut actions = game.valid_actions();
+        actions.retain(|a| !matches!(a, Action::ReserveCard(_)));
+        if actions.is_empty() {
+            // Reserving was the only option, so do nothing instead.
+            return Action::Pass;
+        }
+        self.greedy.choose_from(game, actions)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.greedy.set_think_log(sink);
+    }
+}
+
+// Plays like its greedy agent until an opponent is within ENDGAME_MARGIN VP
+// of the target. From then on, it makes the buy that earns the most VP this
+// turn, nobles included, ignoring long-term card production.
+pub struct EndgameAgent {
+    greedy: GreedyAgent,
+}
+const ENDGAME_MARGIN: u8 = 2;
+impl Agent for EndgameAgent {
+    fn choose_action(&self, game: &GameState) -> Action {
+        let me = game.curr_player_idx;
+        let threatened = game
+            .players
+            .iter()
+            .enumerate()
+            .any(|(i, p)| i != me && p.vp() + ENDGAME_MARGIN >= game.target_vp());
+        if threatened {
+            let best = game
+                .valid_actions()
+                .into_iter()
+                .filter_map(|a| Some((buy_vp(game, &a)?, a)))
+                .max_by_key(|(vp, _)| *vp);
+            if let Some((vp, action)) = best {
+                if vp > 0 {
+                    return action;
+                }
+            }
+        }
+        self.greedy.choose_action(game)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.greedy.set_think_log(sink);
+    }
+}
+
+// Plays the wrapped agent's move with probability `p`, and a random legal
+// move otherwise.
+pub struct NoisyAgent<A: Agent> {
+    inner: A,
+    p: f64,
+    rng: RefCell<StdRng>,
+}
+impl<A: Agent> NoisyAgent<A> {
+    pub fn new(inner: A, p: f64) -> Self {
+        Self::seeded(inner, p, rand::random())
+    }
+    pub fn seeded(inner: A, p: f64, seed: u64) -> Self {
+        Self {
+            inner,
+            p: p.clamp(0.0, 1.0),
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+impl<A: Agent> Agent for NoisyAgent<A> {
+    fn choose_action(&self, game: &GameState) -> Action {
+        let mut rng = self.rng.borrow_mut();
+        if rng.gen_bool(self.p) {
+            return self.inner.choose_action(game);
+        }
+        game.valid_actions()
+            .choose(&mut *rng)
+            .cloned()
+            .unwrap_or(Action::Pass)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.inner.set_think_log(sink);
+    }
+    fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+        if self.rng.borrow_mut().gen_bool(self.p) {
+            return self.inner.choose_action_within(game, deadline);
+        }
+        game.valid_actions()
+            .choose(&mut *self.rng.borrow_mut())
+            .cloned()
+            .unwrap_or(Action::Pass)
+    }
+}
+
+// Searches `depth` of its own moves ahead, assuming the opponents stand
+// still. Moves that draw from a pile are averaged over the cards that could
+// come up, and positions at the search horizon are scored by
+// GameState::evaluate.
+pub struct ExpectimaxAgent {
+    depth: usize,
+}
+impl Agent for ExpectimaxAgent {
+    fn choose_action(&self, game: &GameState) -> Action {
+        self.search(game, self.depth, None)
+    }
+    fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+        deepen(self.depth, deadline, |depth| {
+            self.search(game, depth, Some(deadline))
+        })
+    }
+}
+impl ExpectimaxAgent {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+        }
+    }
+    fn search(&self, game: &GameState, depth: usize, deadline: Option<Instant>) -> Action {
+        let me = game.curr_player_idx;
+        let mut best: Option<(f64, Action)> = None;
+        for action in game.valid_actions() {
+            let value = self.expected_value(game, &action, me, depth, deadline);
+            if best.as_ref().is_none_or(|(v, _)| value > *v) {
+                best = Some((value, action));
+            }
+        }
+        best.map_or(Action::Pass, |(_, a)| a)
+    }
+    fn expected_value(
+        &self,
+        game: &GameState,
+        action: &Action,
+        me: usize,
+        depth: usize,
+        deadline: Option<Instant>,
+    ) -> f64 {
+        let Ok(outcomes) = game.outcomes(action) else {
+            return f64::NEG_INFINITY;
+        };
+        outcomes
+            .into_iter()
+            .map(|(p, mut next)| {
+                // Returning tokens or choosing a noble is part of the same move.
+                let remaining = if next.pending().is_some() {
+                    depth
+                } else {
+                    depth - 1
+                };
+                if !next.is_finished() {
+                    next.curr_player_idx = me;
+                }
+                p * self.value(&next, me, remaining, deadline)
+            })
+            .sum()
+    }
+    fn value(&self, game: &GameState, me: usize, depth: usize, deadline: Option<Instant>) -> f64 {
+        if depth == 0 || game.is_finished() || past(deadline) {
+            return game.evaluate(me) as f64;
+        }
+        game.valid_actions()
+            .iter()
+            .map(|a| self.expected_value(game, a, me, depth, deadline))
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+// Depth-limited alpha-beta search for 2-player games, counting each player's
+// move as one ply. Leaves are scored by GameState::evaluate from its own point
+// of view, finished games count as a win or loss, and pile draws are averaged
+// over the cards that could come up. Other games are played greedily.
+pub struct MinimaxAgent {
+    depth: usize,
+    fallback: GreedyAgent,
+}
+// Added to (or subtracted from) the evaluation of a finished game.
+const WIN_VALUE: f64 = 1000.0;
+impl Agent for MinimaxAgent {
+    fn choose_action(&self, game: &GameState) -> Action {
+        if game.players.len() != 2 {
+            return self.fallback.choose_action(game);
+        }
+        self.search(game, self.depth, None)
+    }
+    fn set_think_log(&mut self, sink: ThinkLog) {
+        self.fallback.set_think_log(sink);
+    }
+    fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+        if game.players.len() != 2 {
+            return self.fallback.choose_action(game);
+        }
+        deepen(self.depth, deadline, |depth| {
+            self.search(game, depth, Some(deadline))
+        })
+    }
+}
+impl MinimaxAgent {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            fallback: GreedyAgent::new(ScoringBonuses::BALANCED),
+        }
+    }
+    fn search(&self, game: &GameState, depth: usize, deadline: Option<Instant>) -> Action {
+        let me = game.curr_player_idx;
+        let mut alpha = f64::NEG_INFINITY;
+        let mut best: Option<(f64, Action)> = None;
+        for action in game.valid_actions() {
+            let Some(value) =
+                self.action_value(game, &action, me, depth, (alpha, f64::INFINITY), deadline)
+            else {
+                continue;
+            };
+            if best.as_ref().is_none_or(|(v, _)| value > *v) {
+                alpha = alpha.max(value);
+                best = Some((value, action));
+            }
+        }
+        best.map_or(Action::Pass, |(_, a)| a)
+    }
+    // Expected value of playing an action, or None if it's illegal. `depth`
+    // counts this move.
+    fn action_value(
+        &self,
+        game: &GameState,
+        action: &Action,
+        me: usize,
+        depth: usize,
+        (alpha, beta): (f64, f64),
+        deadline: Option<Instant>,
+    ) -> Option<f64> {
+        let outcomes =
+            if depth == 1 && !matches!(action, Action::ReserveCard(CardLocation::Pile(_))) {
+                // evaluate() ignores the market, so at the horizon any one refill
+                // stands in for all of them.
+                let mut next = game.clone();
+                next.take_turn(action).ok()?;
+                vec![(1.0, next)]
+            } else {
+                game.outcomes(action).ok()?
+            };
+        // The bounds only carry over when there's no chance node in between.
+        let (alpha, beta) = if outcomes.len() == 1 {
+            (alpha, beta)
+        } else {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        };
+        let value = outcomes
+            .into_iter()
+            .map(|(p, next)| {
+                // Returning tokens or choosing a noble is part of the same move.
+                let remaining = if next.pending().is_some() {
+                    depth
+                } else {
+                    depth - 1
+                };
+                p * self.value(&next, me, remaining, (alpha, beta), deadline)
+            })
+            .sum();
+        Some(value)
+    }
+    fn value(
+        &self,
+        game: &GameState,
+        me: usize,
+        depth: usize,
+        (mut alpha, mut beta): (f64, f64),
+        deadline: Option<Instant>,
+    ) -> f64 {
+        if game.is_finished() {
+            let won = if game.winner() == Some(me) { 1.0 } else { -1.0 };
+            return won * WIN_VALUE + game.evaluate(me) as f64;
+        }
+        if depth == 0 || past(deadline) {
+            return game.evaluate(me) as f64;
+        }
+        let maximizing = game.curr_player_idx == me;
+        let mut best = if maximizing {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        };
+        for action in game.valid_actions() {
+            let Some(value) = self.action_value(game, &action, me, depth, (alpha, beta), deadline)
+            else {
+                continue;
+            };
+            if maximizing {
+                best = best.max(value);
+                alpha = alpha.max(value);
+            } else {
+                best = best.min(value);
+                beta = beta.min(value);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+// Returns the highest-scoring buy that would reach the VP target, if any.
+fn winning_buy(game: &GameState, actions: &[Action]) -> Option<Action> {
+    let vp = game.curr_player().vp();
+    actions
+        .iter()
+        .filter_map(|a| Some((a, vp + buy_vp(game, a)?)))
+        .filter(|(_, vp)| *vp >= game.target_vp())
+        .max_by_key(|(_, vp)| *vp)
+        .map(|(a, _)| a.clone())
+}
+
+// VP a buy would earn this turn, including the best noble it would bring, or
+// None for other actions.
+fn buy_vp(game: &GameState, action: &Action) -> Option<u8> {
+    let Action::BuyCard(loc) = action else {
+        return None;
+    };
+    let card = game.peek_card(loc).ok()?;
+    let mut discounts = game.curr_player().discounts();
+    discounts[card.color as usize] += 1;
+    let noble_vp = game
+        .nobles
+        .iter()
+        .filter(|n| n.cost.iter().zip(discounts).all(|(&c, d)| c <= d))
+        .map(|n| n.vp)
+        .max()
+        .unwrap_or(0);
+    Some(card.vp + noble_vp)
+}
+
 struct ScoringBonuses {
     vp: i32,
     card_needed: i32,
     color_needed: i32,
     reserve_discount: i32,
+    // Penalty per token held beyond the hoard limit after taking tokens, applied
+    // only when nothing is affordable right now.
+    hoard_penalty: i32,
+    // Penalty per token a take would push over the cap, since those are
+    // discarded straight away.
+    discard_penalty: i32,
+    // Score for reserving a blind card off a pile, which is almost never a
+    // good idea.
+    pile_reserve_penalty: i32,
+}
+
+// Holding more than this many tokens below the cap risks running into it.
+const HOARD_MARGIN: i32 = 2;
+
+impl ScoringBonuses {
+    const GREEDY: Self = Self {
+        vp: 100,
+        card_needed: 0,
+        color_needed: 0,
+        reserve_discount: 10,
+        hoard_penalty: 0,
+        discard_penalty: 0,
+        pile_reserve_penalty: 1,
+    };
+    const BALANCED: Self = Self {
+        vp: 1000,
+        card_needed: 10,
+        color_needed: 1,
+        reserve_discount: 10,
+        hoard_penalty: 3,
+        discard_penalty: 30,
+        pile_reserve_penalty: 1,
+    };
 }
 
 struct ScoringInfo {
@@ -83,11 +597,18 @@ struct ScoringInfo {
     cards_needed: [i32; 5],
     // Count of token colors needed (excluding gold) for card purchasing.
     colors_needed: [i32; 5],
+    // Tokens currently held.
+    num_tokens: i32,
+    // Holding more tokens than this risks running into the token cap.
+    hoard_limit: i32,
+    max_tokens: i32,
+    // Whether any market or reserved card is affordable right now.
+    can_buy: bool,
 }
 impl ScoringInfo {
     fn new(game: &GameState) -> Self {
         let me = game.curr_player();
-        let cards = me.purchasing_power(false);
+        let cards = me.discounts();
         let mut cards_needed = [0, 0, 0, 0, 0];
         for n in game.nobles.iter() {
             for (i, c) in n.cost.iter().enumerate() {
@@ -107,33 +628,55 @@ impl ScoringInfo {
                 }
             }
         }
+        let can_buy = !me.buyable_reserved_cards().is_empty()
+            || game.market.iter().flatten().any(|c| me.can_buy(c));
         Self {
             cards_needed,
             colors_needed,
+            num_tokens: me.num_tokens() as i32,
+            hoard_limit: game.ruleset().max_tokens as i32 - HOARD_MARGIN,
+            max_tokens: game.ruleset().max_tokens as i32,
+            can_buy,
+        }
+    }
+
+    fn hoard_penalty(&self, taking: usize, bonuses: &ScoringBonuses) -> i32 {
+        let after = self.num_tokens + taking as i32;
+        let discarded = (after - self.max_tokens).max(0) * bonuses.discard_penalty;
+        if self.can_buy {
+            return discarded;
         }
+        discarded + (after - self.hoard_limit).max(0) * bonuses.hoard_penalty
     }
 
     fn score_action(&self, game: &GameState, action: &Action, bonuses: &ScoringBonuses) -> i32 {
         match action {
-            Action::TakeDifferentColorTokens(colors) => colors
-                .iter()
-                .map(|c| self.colors_needed[*c as usize] * bonuses.color_needed)
-                .sum(),
+            Action::TakeDifferentColorTokens(colors) => {
+                colors
+                    .iter()
+                    .map(|c| self.colors_needed[*c as usize] * bonuses.color_needed)
+                    .sum::<i32>()
+                    - self.hoard_penalty(colors.len(), bonuses)
+            }
             Action::TakeSameColorTokens(color) => {
                 self.colors_needed[*color as usize] * bonuses.color_needed
+                    - self.hoard_penalty(2, bonuses)
             }
-            Action::BuyCard(loc) => {
+            Action::BuyCard(loc) | Action::BuyCardWithPayment(loc, _) => {
                 let card = game.peek_card(loc).unwrap();
                 // Prefer cards in the reserve, but only a tiny bit.
                 let loc_bonus = match loc {
                     CardLocation::Reserve(_) => 1,
                     _ => 0,
                 };
+                // Also save gold for later, again only a tiny bit.
+                let gold = game.curr_player().gold_needed(card).unwrap_or(0) as i32;
                 let idx = card.color as usize;
                 card.vp as i32 * bonuses.vp
                     + self.cards_needed[idx] * bonuses.card_needed
                     + self.colors_needed[idx] * bonuses.color_needed
                     + loc_bonus
+                    - gold
             }
             Action::ReserveCard(loc) => {
                 if let Ok(card) = game.peek_card(loc) {
@@ -141,10 +684,471 @@ impl ScoringInfo {
                     (card.vp as i32 * bonuses.vp + self.cards_needed[idx] * bonuses.card_needed)
                         / bonuses.reserve_discount
                 } else {
-                    // Reserving from the pile is almost never a good idea.
-                    -1
+                    -bonuses.pile_reserve_penalty
                 }
             }
+            Action::Pass => 0,
+            Action::ReturnTokens(colors) => {
+                // Give back the tokens we need least, and gold only as a last
+                // resort since it substitutes for any color.
+                let most_needed = self.colors_needed.iter().max().copied().unwrap_or(0);
+                -colors
+                    .iter()
+                    .map(|&c| match c {
+                        Color::Gold => (most_needed + 1) * bonuses.color_needed,
+                        _ => self.colors_needed[c as usize] * bonuses.color_needed,
+                    })
+                    .sum::<i32>()
+            }
+            Action::AcquireNoble(idx) => {
+                game.nobles.get(*idx).map_or(0, |n| n.vp as i32) * bonuses.vp
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{Card, Noble, PendingChoice};
+    use crate::testing::GameStateBuilder;
+    use std::sync::{Arc, Mutex};
+
+    // Always plays the first legal action, for deterministic comparisons.
+    struct FirstAgent;
+    impl Agent for FirstAgent {
+        fn choose_action(&self, game: &GameState) -> Action {
+            game.valid_actions()[0].clone()
+        }
+    }
+    // Never returns a legal action, so it must never be consulted.
+    struct IllegalAgent;
+    impl Agent for IllegalAgent {
+        fn choose_action(&self, _game: &GameState) -> Action {
+            Action::TakeSameColorTokens(Color::Gold)
+        }
+    }
+
+    #[test]
+    fn noisy_agent_extremes() {
+        let always = NoisyAgent::seeded(FirstAgent, 1.0, 7);
+        let never = NoisyAgent::seeded(IllegalAgent, 0.0, 7);
+        let mut deviated = false;
+        let mut gs = GameState::init_seeded(2, 7).unwrap();
+        for _ in 0..40 {
+            if gs.is_finished() {
+                break;
+            }
+            let expected = FirstAgent.choose_action(&gs);
+            assert_eq!(always.choose_action(&gs), expected);
+            let action = never.choose_action(&gs);
+            assert!(gs.valid_actions().contains(&action));
+            deviated |= action != expected;
+            gs.take_turn(&action).unwrap();
+        }
+        assert!(deviated);
+    }
+
+    #[test]
+    fn epsilon_greedy_extremes() {
+        // Fully greedy: always a move the greedy agent could have made. It
+        // breaks ties randomly, so compare scores rather than moves.
+        let agent = epsilon_greedy(0.0, 3);
+        let greedy = GreedyAgent::new(ScoringBonuses::GREEDY);
+        let mut gs = GameState::init_seeded(2, 3).unwrap();
+        for _ in 0..40 {
+            if gs.is_finished() {
+                break;
+            }
+            let action = agent.choose_action(&gs);
+            let info = ScoringInfo::new(&gs);
+            let score = |a: &Action| info.score_action(&gs, a, &ScoringBonuses::GREEDY);
+            assert_eq!(score(&action), score(&greedy.choose_action(&gs)));
+            gs.take_turn(&action).unwrap();
+        }
+
+        // Fully random: uniform over the legal moves, like RandomAgent.
+        let gs = GameState::init_seeded(2, 3).unwrap();
+        let actions = gs.valid_actions();
+        let trials = 200 * actions.len();
+        let mut counts = vec![0; actions.len()];
+        let mut random_counts = vec![0; actions.len()];
+        let count = |counts: &mut Vec<usize>, action: Action| {
+            counts[actions.iter().position(|a| *a == action).unwrap()] += 1;
+        };
+        for seed in 0..trials as u64 {
+            count(&mut counts, epsilon_greedy(1.0, seed).choose_action(&gs));
+            count(&mut random_counts, RandomAgent.choose_action(&gs));
+        }
+        for (&n, &r) in counts.iter().zip(&random_counts) {
+            assert!((100..300).contains(&n), "{:?}", counts);
+            assert!((100..300).contains(&r), "{:?}", random_counts);
+        }
+    }
+
+    #[test]
+    fn agents_by_name() {
+        let game = GameState::init(2).unwrap();
+        for (name, _) in AGENT_NAMES {
+            let agent = create_agent_by_name(name).unwrap();
+            assert!(game.valid_actions().contains(&agent.choose_action(&game)));
+        }
+        assert!(create_agent_by_name("mcts").is_none());
+        assert!(create_agent_by_name("").is_none());
+    }
+
+    #[test]
+    fn greedy_takes_winning_buy() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.players[idx].vp_history.push((1, 13));
+        gs.players[idx].tokens = [2, 2, 0, 0, 0, 0];
+        gs.market[2][1] = Card {
+            id: 0,
+            level: 3,
+            color: Color::Red,
+            vp: 2,
+            cost: [2, 2, 0, 0, 0],
+        };
+        for difficulty in 1..=2 {
+            let agent = create_agent(difficulty);
+            assert_eq!(
+                agent.choose_action(&gs),
+                Action::BuyCard(CardLocation::Market(3, 1))
+            );
+        }
+    }
+
+    #[test]
+    fn no_reserve_agent_never_reserves() {
+        let agent = create_agent(10);
+        let mut gs = GameState::init(2).unwrap();
+        for _turn in 1..=1000 {
+            let action = agent.choose_action(&gs);
+            assert!(!matches!(action, Action::ReserveCard(_)));
+            // Without reserving, agents can end up stuck passing forever,
+            // so the game may not finish within the turn limit.
+            if gs.take_turn(&action).unwrap().is_over {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn greedy_think_log() {
+        let gs = GameState::init(2).unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = entries.clone();
+        let mut agent = create_agent(2);
+        agent.set_think_log(Box::new(move |idx, action, score| {
+            sink.lock().unwrap().push((idx, action.clone(), score));
+        }));
+        let chosen = agent.choose_action(&gs);
+        let entries = entries.lock().unwrap();
+        let candidates = gs.valid_actions();
+        assert_eq!(entries.len(), candidates.len());
+        for ((idx, action, _), candidate) in entries.iter().zip(candidates.iter()) {
+            assert_eq!(*idx, gs.curr_player_idx);
+            assert_eq!(action, candidate);
+        }
+        let best = entries.iter().map(|(_, _, s)| *s).max().unwrap();
+        assert!(entries.iter().any(|(_, a, s)| *a == chosen && *s == best));
+    }
+
+    #[test]
+    fn pile_reserve_penalty() {
+        let mut gs = GameState::init(2).unwrap();
+        gs.nobles.clear();
+        for row in gs.market.iter_mut() {
+            row.clear();
+        }
+        // Nothing to buy or aim for, so every token take scores zero.
+        let is_pile_reserve = |a: &Action| matches!(a, Action::ReserveCard(CardLocation::Pile(_)));
+        let penalized = GreedyAgent::new(ScoringBonuses::BALANCED);
+        assert!((0..100).all(|_| !is_pile_reserve(&penalized.choose_action(&gs))));
+        let unpenalized = GreedyAgent::new(ScoringBonuses {
+            pile_reserve_penalty: 0,
+            ..ScoringBonuses::BALANCED
+        });
+        assert!((0..100).any(|_| is_pile_reserve(&unpenalized.choose_action(&gs))));
+    }
+
+    #[test]
+    fn endgame_grabs_vp() {
+        let card = |level, color, vp, cost| Card {
+            id: 0,
+            level,
+            color,
+            vp,
+            cost,
+        };
+        let pricey = |level| vec![card(level, Color::White, 0, [7; 5]); 4];
+        // A free 1 VP card, or a free red card that brings a 3 VP noble.
+        let mut row = pricey(1);
+        row[0] = card(1, Color::White, 1, [0; 5]);
+        row[1] = card(1, Color::Red, 0, [0; 5]);
+        let builder = GameStateBuilder::new(2)
+            .owned(0, vec![card(1, Color::Red, 0, [1; 5]); 2])
+            .nobles(vec![Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 0, 3, 0],
+            }])
+            .market(1, row)
+            .market(2, pricey(2))
+            .market(3, pricey(3))
+            .curr_player(0);
+        let agent = create_agent(5);
+        let gs = builder.clone().build().unwrap();
+        assert_eq!(
+            agent.choose_action(&gs),
+            Action::BuyCard(CardLocation::Market(1, 0))
+        );
+        // Once the opponent is close to winning, the noble is worth more.
+        let gs = builder
+            .owned(
+                1,
+                vec![
+                    card(3, Color::Blue, 5, [0; 5]),
+                    card(3, Color::Blue, 4, [0; 5]),
+                    card(3, Color::Blue, 4, [0; 5]),
+                ],
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            agent.choose_action(&gs),
+            Action::BuyCard(CardLocation::Market(1, 1))
+        );
+    }
+
+    #[test]
+    fn greedy_saves_gold() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.nobles.clear();
+        gs.players[idx].tokens = [1, 0, 0, 0, 0, 1];
+        for row in gs.market.iter_mut() {
+            for card in row.iter_mut() {
+                card.vp = 0;
+                card.cost = [7, 7, 7, 7, 7];
+            }
+        }
+        // Two otherwise equal cards, but only one can be bought without gold.
+        let card = |cost| Card {
+            id: 0,
+            level: 1,
+            color: Color::Green,
+            vp: 1,
+            cost,
+        };
+        gs.market[0][0] = card([0, 1, 0, 0, 0]);
+        gs.market[0][1] = card([1, 0, 0, 0, 0]);
+        let agent = GreedyAgent::new(ScoringBonuses::BALANCED);
+        for _ in 0..10 {
+            assert_eq!(
+                agent.choose_action(&gs),
+                Action::BuyCard(CardLocation::Market(1, 1))
+            );
+        }
+    }
+
+    #[test]
+    fn hoarding_penalty() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        let take_three =
+            Action::TakeDifferentColorTokens(vec![Color::White, Color::Blue, Color::Green]);
+        let untuned = ScoringBonuses {
+            hoard_penalty: 0,
+            ..ScoringBonuses::BALANCED
+        };
+        let tuned = ScoringBonuses::BALANCED;
+
+        // Few tokens: no penalty.
+        let info = ScoringInfo::new(&gs);
+        assert_eq!(
+            info.score_action(&gs, &take_three, &tuned),
+            info.score_action(&gs, &take_three, &untuned)
+        );
+
+        // Near the cap with nothing affordable: taking 3 lands on 10 tokens.
+        gs.players[idx].tokens = [0, 0, 0, 0, 7, 0];
+        gs.market[0].clear();
+        gs.market[1].clear();
+        gs.market[2].clear();
+        let info = ScoringInfo::new(&gs);
+        assert_eq!(
+            info.score_action(&gs, &take_three, &tuned),
+            info.score_action(&gs, &take_three, &untuned) - 2 * tuned.hoard_penalty
+        );
+        let take_two = Action::TakeSameColorTokens(Color::Red);
+        assert_eq!(
+            info.score_action(&gs, &take_two, &tuned),
+            info.score_action(&gs, &take_two, &untuned) - tuned.hoard_penalty
+        );
+
+        // With an affordable card, there's no penalty.
+        gs.market[0].push(Card {
+            id: 0,
+            level: 1,
+            color: Color::Red,
+            vp: 0,
+            cost: [0, 0, 0, 0, 3],
+        });
+        let info = ScoringInfo::new(&gs);
+        assert_eq!(
+            info.score_action(&gs, &take_three, &tuned),
+            info.score_action(&gs, &take_three, &untuned)
+        );
+    }
+
+    // Turns across 100 seeded self-play games that ended over the token cap,
+    // forcing a discard.
+    fn forced_discards(bonuses: ScoringBonuses) -> usize {
+        let agent = GreedyAgent::new(bonuses);
+        let mut discards = 0;
+        for seed in 0..100 {
+            let mut gs = GameState::init_seeded(2, seed).unwrap();
+            while !gs.is_finished() && gs.turn_count() < 200 {
+                gs.take_turn(&agent.choose_action(&gs)).unwrap();
+                if matches!(gs.pending(), Some(PendingChoice::ReturnTokens(_))) {
+                    discards += 1;
+                }
+            }
+        }
+        discards
+    }
+
+    #[test]
+    fn hoarding_penalty_avoids_discards() {
+        let untuned = forced_discards(ScoringBonuses {
+            hoard_penalty: 0,
+            discard_penalty: 0,
+            ..ScoringBonuses::BALANCED
+        });
+        let tuned = forced_discards(ScoringBonuses::BALANCED);
+        assert!(2 * tuned < untuned, "{} vs {}", tuned, untuned);
+    }
+
+    #[test]
+    fn expectimax_reserves_to_buy_later() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.nobles.clear();
+        gs.players[idx].tokens = [2, 0, 0, 0, 0, 0];
+        for row in gs.market.iter_mut() {
+            for card in row.iter_mut() {
+                card.vp = 0;
+                card.cost = [7, 7, 7, 7, 7];
+            }
+        }
+        // A free 1 VP card now, or a 5 VP card that needs one gold token.
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Blue,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        gs.market[2][0] = Card {
+            id: 0,
+            level: 3,
+            color: Color::Red,
+            vp: 5,
+            cost: [3, 0, 0, 0, 0],
+        };
+        let greedy = create_agent(2).choose_action(&gs);
+        assert_eq!(greedy, Action::BuyCard(CardLocation::Market(1, 0)));
+        assert_eq!(ExpectimaxAgent::new(1).choose_action(&gs), greedy);
+
+        // Looking two moves ahead, reserving (for the gold) comes first, since
+        // it makes the 5 VP card affordable.
+        let agent = ExpectimaxAgent::new(2);
+        let action = agent.choose_action(&gs);
+        assert!(matches!(action, Action::ReserveCard(_)));
+        gs.take_turn(&action).unwrap();
+        // The 5 VP card is bought within the next two moves.
+        for _ in 0..2 {
+            gs.curr_player_idx = idx;
+            gs.take_turn(&agent.choose_action(&gs)).unwrap();
+        }
+        assert!(gs.players[idx].vp() >= 5);
+    }
+
+    #[test]
+    fn minimax_blocks_winning_reply() {
+        let mut gs = GameState::init(2).unwrap();
+        let (me, opponent) = (gs.curr_player_idx, 1 - gs.curr_player_idx);
+        assert_eq!(gs.first_player(), me);
+        gs.nobles.clear();
+        for row in gs.market.iter_mut() {
+            for card in row.iter_mut() {
+                card.vp = 0;
+                card.cost = [7, 7, 7, 7, 7];
+            }
+        }
+        // We can buy a 2 VP card, but the opponent (at 14 VP, and last to
+        // play this round) could then buy the 1 VP card and win.
+        gs.players[me].tokens = [3, 0, 0, 0, 0, 0];
+        gs.players[opponent].tokens = [0, 0, 0, 0, 3, 0];
+        gs.players[opponent].vp_history.push((1, 14));
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Black,
+            vp: 1,
+            cost: [0, 0, 0, 0, 3],
+        };
+        gs.market[1][0] = Card {
+            id: 0,
+            level: 2,
+            color: Color::White,
+            vp: 2,
+            cost: [3, 0, 0, 0, 0],
+        };
+        let tempting = Action::BuyCard(CardLocation::Market(2, 0));
+        assert_eq!(create_agent(2).choose_action(&gs), tempting);
+        let block = Action::ReserveCard(CardLocation::Market(1, 0));
+        assert_eq!(MinimaxAgent::new(2).choose_action(&gs), block);
+    }
+
+    // Spends 20ms considering each legal action in turn, but stops once the
+    // deadline passes.
+    struct SlowAgent;
+    impl Agent for SlowAgent {
+        fn choose_action(&self, game: &GameState) -> Action {
+            self.choose_action_within(game, Instant::now() + Duration::from_secs(3600))
+        }
+        fn choose_action_within(&self, game: &GameState, deadline: Instant) -> Action {
+            let actions = game.valid_actions();
+            let mut best = actions[0].clone();
+            for action in actions {
+                if Instant::now() >= deadline {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+                best = action;
+            }
+            best
+        }
+    }
+
+    #[test]
+    fn time_limited_agents_meet_the_deadline() {
+        let gs = GameState::init(2).unwrap();
+        let agents: Vec<Box<dyn Agent>> = vec![
+            Box::new(SlowAgent),
+            Box::new(ExpectimaxAgent::new(10)),
+            Box::new(MinimaxAgent::new(10)),
+        ];
+        // With no time at all, each still falls back to a legal move.
+        for agent in agents {
+            let agent = TimeLimited::new(agent, Duration::ZERO);
+            let action = agent.choose_action(&gs);
+            assert_eq!(gs.validate_action(&action), Ok(()));
+            let action = agent.choose_action_within(&gs, Instant::now());
+            assert_eq!(gs.validate_action(&action), Ok(()));
         }
     }
 }
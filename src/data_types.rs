@@ -1,22 +1,306 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(from = "RawAction")]
 pub enum Action {
     TakeDifferentColorTokens(Vec<Color>),
     TakeSameColorTokens(Color),
     ReserveCard(CardLocation),
     BuyCard(CardLocation),
+    // Buy a card, paying exactly these tokens (indexed by color, gold last)
+    // instead of letting the engine spend colored tokens before gold.
+    BuyCardWithPayment(CardLocation, [u8; 6]),
+    // Do nothing, when no other action is possible.
+    Pass,
+    // Discard back down to the token limit after taking tokens.
+    ReturnTokens(Vec<Color>),
+    // Choose which noble (by index into the available nobles) to acquire,
+    // when several qualify.
+    AcquireNoble(usize),
+}
+// Wire format for Action. Older clients pass by taking zero tokens, so an
+// empty TakeDifferentColorTokens is read back as Pass.
+#[derive(Deserialize)]
+enum RawAction {
+    TakeDifferentColorTokens(Vec<Color>),
+    TakeSameColorTokens(Color),
+    ReserveCard(CardLocation),
+    BuyCard(CardLocation),
+    BuyCardWithPayment(CardLocation, [u8; 6]),
+    Pass,
+    ReturnTokens(Vec<Color>),
+    AcquireNoble(usize),
+}
+impl From<RawAction> for Action {
+    fn from(raw: RawAction) -> Self {
+        match raw {
+            RawAction::TakeDifferentColorTokens(colors) if colors.is_empty() => Action::Pass,
+            RawAction::TakeDifferentColorTokens(colors) => Action::TakeDifferentColorTokens(colors),
+            RawAction::TakeSameColorTokens(color) => Action::TakeSameColorTokens(color),
+            RawAction::ReserveCard(loc) => Action::ReserveCard(loc),
+            RawAction::BuyCard(loc) => Action::BuyCard(loc),
+            RawAction::BuyCardWithPayment(loc, payment) => Action::BuyCardWithPayment(loc, payment),
+            RawAction::Pass => Action::Pass,
+            RawAction::ReturnTokens(colors) => Action::ReturnTokens(colors),
+            RawAction::AcquireNoble(idx) => Action::AcquireNoble(idx),
+        }
+    }
+}
+impl Action {
+    // Canonical constructor for taking different colors, in color order.
+    pub fn take_different(colors: &[Color]) -> Action {
+        Action::TakeDifferentColorTokens(colors.to_vec()).normalized()
+    }
+    // Token order doesn't matter when taking different colors or returning
+    // tokens, so sort it to make equivalent actions compare (and hash) equal.
+    pub fn normalized(mut self) -> Action {
+        if let Action::TakeDifferentColorTokens(colors) | Action::ReturnTokens(colors) = &mut self {
+            colors.sort();
+        }
+        self
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+// Variant rules for a game.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ruleset {
+    // Victory points needed to trigger the end of the game.
+    pub target_vp: u8,
+    // Players holding more tokens than this at the end of a turn must return
+    // some. Older serialized rulesets use the standard cap.
+    #[serde(default = "Ruleset::default_max_tokens")]
+    pub max_tokens: u8,
+}
+impl Ruleset {
+    // The standard token cap.
+    pub const MAX_TOKENS: u8 = 10;
+    pub fn standard() -> Self {
+        Self {
+            target_vp: 15,
+            max_tokens: Self::MAX_TOKENS,
+        }
+    }
+    // A shorter game, played to 10 VP.
+    pub fn quick() -> Self {
+        Self {
+            target_vp: 10,
+            ..Self::standard()
+        }
+    }
+    fn default_max_tokens() -> u8 {
+        Self::MAX_TOKENS
+    }
+}
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+// Everything needed to set up a new game.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GameConfig {
+    pub num_players: usize,
+    pub target_vp: u8,
+    // Deal from this seed, or from a random one if unset.
+    pub seed: Option<u64>,
+    // Starting bank, instead of the standard one for the player count. No
+    // more tokens than come in the box: 7 of each color and 5 gold.
+    #[serde(default)]
+    pub bank: Option<[u8; 6]>,
+}
+impl GameConfig {
+    pub const MAX_BANK: [u8; 6] = [7, 7, 7, 7, 7, 5];
+    pub fn new(num_players: usize) -> Self {
+        Self {
+            num_players,
+            target_vp: Ruleset::standard().target_vp,
+            seed: None,
+            bank: None,
+        }
+    }
+}
+
+// Counts of legal actions, by category.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ActionSummary {
+    pub buys: usize,
+    pub reserves: usize,
+    pub token_takes: usize,
+    pub can_pass: bool,
+    #[serde(default)]
+    pub returns: usize,
+    #[serde(default)]
+    pub nobles: usize,
+}
+
+// Profiling counters for a GameState, from the "metrics" feature.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub valid_actions_calls: u64,
+    // Longest action list valid_actions has returned.
+    pub max_actions: usize,
+}
+
+// A player's public standing, for scoreboards. Reserved cards are only
+// counted, since opponents can't see them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlayerSummary {
+    pub vp: u8,
+    // Tokens held: [white, blue, green, red, black, gold]
+    pub tokens: [u8; 6],
+    // Purchased cards: [white, blue, green, red, black]
+    pub cards: [u8; 5],
+    pub nobles: usize,
+    pub reserved: usize,
+}
+
+// How far a player is from affording a card.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Affordability {
+    // Tokens of each color still needed after discounts and colored tokens.
+    pub missing: [u8; 5],
+    // Gold tokens still needed after spending the player's gold on `missing`.
+    pub shortfall: u8,
+}
+impl Affordability {
+    pub fn can_afford(&self) -> bool {
+        self.shortfall == 0
+    }
+}
+
+// Who a serialized game state is meant for, which decides what is hidden.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Audience {
+    // A seated player: opponents' reserved cards are hidden.
+    Player(usize),
+    // An observer who sees every player's hand, but not the piles.
+    Spectator,
+    // Everything, including the contents of the piles and the deal seed.
+    Full,
+}
+
+// Why an action was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionError {
+    GameOver,
+    MustReturnTokens,
+    MustChooseNoble,
+    NothingToReturn,
+    NoNobleToChoose,
+    TooManyTokens,
+    GoldNotTakeable,
+    DuplicateColor,
+    InsufficientBank,
+    AlreadyReserved,
+    ReserveFull,
+    BuyFromPile,
+    CannotAfford,
+    InvalidPayment,
+    WrongReturnCount(u8),
+    ReturnNotHeld,
+    NobleUnavailable,
+    // The card location doesn't exist, with a description of why.
+    InvalidLocation(String),
+}
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionError::GameOver => write!(f, "Game is over"),
+            ActionError::MustReturnTokens => write!(f, "Must return tokens first"),
+            ActionError::MustChooseNoble => write!(f, "Must choose a noble first"),
+            ActionError::NothingToReturn => write!(f, "No tokens to return"),
+            ActionError::NoNobleToChoose => write!(f, "No noble to choose"),
+            ActionError::TooManyTokens => write!(f, "Cannot take more than 3 tokens"),
+            ActionError::GoldNotTakeable => write!(f, "Cannot take a gold token"),
+            ActionError::DuplicateColor => write!(f, "Cannot take the same color twice"),
+            ActionError::InsufficientBank => write!(f, "Not enough tokens in bank"),
+            ActionError::AlreadyReserved => write!(f, "Card is already reserved"),
+            ActionError::ReserveFull => write!(f, "At most 3 cards can be reserved"),
+            ActionError::BuyFromPile => write!(f, "Cannot buy a card from the pile"),
+            ActionError::CannotAfford => write!(f, "Cannot afford card"),
+            ActionError::InvalidPayment => write!(f, "Payment does not match the card's cost"),
+            ActionError::WrongReturnCount(n) => write!(f, "Must return exactly {} tokens", n),
+            ActionError::ReturnNotHeld => write!(f, "Cannot return tokens you don't have"),
+            ActionError::NobleUnavailable => write!(f, "Cannot acquire that noble"),
+            ActionError::InvalidLocation(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+impl std::error::Error for ActionError {}
+
+// A decision the current player must make before their turn can end.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PendingChoice {
+    // Return this many tokens to get back down to the token limit.
+    ReturnTokens(u8),
+    // Pick one of several nobles the player qualifies for.
+    AcquireNoble,
+}
+
+// Why a game ended.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GameOutcome {
+    // A player reached the VP target and the final round completed.
+    VictoryThreshold,
+    // The game was cut off after too many turns.
+    Timeout,
+    // Every player in turn had no move but to pass, so no one could ever
+    // move again. The winner is decided by the current VP.
+    Stalemate,
+}
+
+// The result of a single take_turn call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TurnOutcome {
+    pub is_over: bool,
+    // The noble the acting player acquired this turn, if any.
+    pub noble: Option<Noble>,
+    // VP the acting player gained, from cards and nobles.
+    pub vp_gained: u8,
+    // The card the acting player bought or reserved, if any.
+    pub card: Option<Card>,
+    // Whether reserving a card gave the acting player a gold token.
+    #[serde(default)]
+    pub gold_granted: bool,
+}
+
+// Something that happened while processing an action, for hosts that want
+// typed notifications. Serialize an event to get its JSON form.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GameEvent {
+    TurnTaken {
+        player_idx: usize,
+        action: Action,
+        // Every player's VP after the action.
+        new_scores: Vec<u8>,
+        // Whether reserving a card gave the player a gold token.
+        #[serde(default)]
+        gold_granted: bool,
+    },
+    NobleAcquired {
+        player_idx: usize,
+        noble: Noble,
+    },
+    GameOver {
+        winners: Vec<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum CardLocation {
     Pile(usize),
     Market(usize, usize),
     Reserve(usize),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Card {
+    // Stable identifier, e.g. for artwork or logs. Zero when unknown or
+    // hidden.
+    #[serde(default)]
+    pub id: u16,
     pub level: usize,
     // Production color
     pub color: Color,
@@ -26,15 +310,61 @@ pub struct Card {
     pub cost: [u8; 5],
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Card {
+    pub fn cost_string(&self) -> String {
+        cost_string(&self.cost)
+    }
+}
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sym = self.color.symbol();
+        write!(
+            f,
+            "{}{} {}VP ({})",
+            sym,
+            self.level,
+            self.vp,
+            self.cost_string()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Noble {
+    // Stable identifier, like Card::id.
+    #[serde(default)]
+    pub id: u16,
     // Victory points
     pub vp: u8,
     // Cost to acquire: [white, blue, green, red, black]
     pub cost: [u8; 5],
 }
+impl Noble {
+    pub fn cost_string(&self) -> String {
+        cost_string(&self.cost)
+    }
+}
+impl fmt::Display for Noble {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Noble {}VP ({})", self.vp, self.cost_string())
+    }
+}
+
+// Formats a cost like "2W 1R 3K", omitting colors that cost nothing.
+fn cost_string(cost: &[u8; 5]) -> String {
+    let parts = cost
+        .iter()
+        .enumerate()
+        .filter(|(_, &n)| n > 0)
+        .map(|(i, n)| format!("{}{}", n, Color::try_from(i).unwrap().symbol()))
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        return "free".to_string();
+    }
+    parts.join(" ")
+}
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum Color {
     White,
@@ -44,6 +374,24 @@ pub enum Color {
     Black,
     Gold,
 }
+impl Color {
+    // Single-letter symbol, using K for black to avoid clashing with blue.
+    pub fn symbol(&self) -> char {
+        match self {
+            Color::White => 'W',
+            Color::Blue => 'B',
+            Color::Green => 'G',
+            Color::Red => 'R',
+            Color::Black => 'K',
+            Color::Gold => '*',
+        }
+    }
+    pub fn from_symbol(symbol: char) -> Option<Color> {
+        (0..6)
+            .map(|i| Color::try_from(i).unwrap())
+            .find(|c| c.symbol() == symbol)
+    }
+}
 impl TryFrom<usize> for Color {
     type Error = ();
     fn try_from(value: usize) -> Result<Self, Self::Error> {
@@ -58,3 +406,59 @@ impl TryFrom<usize> for Color {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_strings() {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 0,
+            cost: [2, 0, 0, 1, 3],
+        };
+        assert_eq!(card.cost_string(), "2W 1R 3K");
+        assert_eq!(card.to_string(), "W1 0VP (2W 1R 3K)");
+        let card = Card {
+            cost: [0, 0, 0, 0, 0],
+            ..card
+        };
+        assert_eq!(card.cost_string(), "free");
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 3, 3, 3, 0],
+        };
+        assert_eq!(noble.cost_string(), "3B 3G 3R");
+        assert_eq!(noble.to_string(), "Noble 3VP (3B 3G 3R)");
+    }
+
+    #[test]
+    fn normalized_take_actions() {
+        let a = Action::TakeDifferentColorTokens(vec![Color::Red, Color::White, Color::Blue]);
+        let b = Action::TakeDifferentColorTokens(vec![Color::Blue, Color::Red, Color::White]);
+        assert_ne!(a, b);
+        assert_eq!(a.clone().normalized(), b.normalized());
+        assert_eq!(
+            Action::take_different(&[Color::Red, Color::White, Color::Blue]),
+            Action::TakeDifferentColorTokens(vec![Color::White, Color::Blue, Color::Red])
+        );
+        let c = Action::TakeSameColorTokens(Color::Red);
+        assert_eq!(c.clone().normalized(), c);
+    }
+
+    #[test]
+    fn legacy_pass_deserializes() {
+        let action: Action = serde_json::from_str(r#"{"TakeDifferentColorTokens": []}"#).unwrap();
+        assert_eq!(action, Action::Pass);
+        assert_eq!(serde_json::to_string(&Action::Pass).unwrap(), r#""Pass""#);
+        let action: Action = serde_json::from_str(r#""Pass""#).unwrap();
+        assert_eq!(action, Action::Pass);
+        let take = Action::take_different(&[Color::White, Color::Red]);
+        let json = serde_json::to_string(&take).unwrap();
+        assert_eq!(serde_json::from_str::<Action>(&json).unwrap(), take);
+    }
+}
@@ -1,14 +1,34 @@
-use crate::data_types::{Action, Card, CardLocation, Color, Noble};
+use crate::agent::Agent;
+#[cfg(feature = "metrics")]
+use crate::data_types::Metrics;
+use crate::data_types::{
+    Action, ActionError, ActionSummary, Audience, Card, CardLocation, Color, GameConfig, GameEvent,
+    GameOutcome, Noble, PendingChoice, PlayerSummary, Ruleset, TurnOutcome,
+};
 use crate::player::Player;
-use rand::{prelude::SliceRandom, seq::IteratorRandom};
+#[cfg(feature = "bincode")]
+use bincode::Options;
+use rand::{prelude::SliceRandom, rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "metrics")]
+use std::cell::Cell;
 
 type DynError = Box<dyn std::error::Error>;
 
+// Taking two tokens of one color requires at least this many in the bank.
+const SAME_COLOR_BANK_MIN: u8 = 4;
+// Largest supported game. The bank sizes and the built-in noble deck (which
+// needs a noble per player plus one) only cover the real game's 2-4 players.
+const MAX_PLAYERS: usize = 4;
+// Simulated games are cut off after this many actions.
+const MAX_SIMULATED_TURNS: usize = 1000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
-    // 3 piles of cards, one per level, face down.
-    #[serde(skip)]
+    // 3 piles of cards, one per level, face down. These are saved so a
+    // restored game can continue, but serialize_for hides them from players.
+    // Older serialized games have none.
+    #[serde(default)]
     piles: [Vec<Card>; 3],
 
     // 3 rows of buyable cards, 4 per level, face up.
@@ -24,21 +44,108 @@ pub struct GameState {
     pub players: Vec<Player>,
     pub curr_player_idx: usize,
 
+    // The player who took the first turn. A round ends when play comes back
+    // around to them. Older serialized games always started with player 0.
+    #[serde(default)]
+    first_player: usize,
+
     // Current round number.
     #[serde(default)]
     round: u16,
+
+    // Completed turns, counting a turn's follow-up choices as part of it.
+    #[serde(default)]
+    turns: u32,
+
+    // Turns in a row that were forced passes.
+    #[serde(default)]
+    passes: usize,
+
+    // Set once the final round completes. curr_player_idx stays a valid seat
+    // either way.
+    #[serde(default)]
+    finished: bool,
+
+    // Why the game ended, once it has.
+    #[serde(default)]
+    outcome: Option<GameOutcome>,
+
+    // Variant rules. Older serialized games use the standard rules.
+    #[serde(default)]
+    ruleset: Ruleset,
+
+    // Seed used to deal the game, if it was dealt from one.
+    #[serde(default)]
+    seed: Option<u64>,
+
+    // A choice the current player must make to finish their turn.
+    #[serde(default)]
+    pending: Option<PendingChoice>,
+
+    // Every (player index, action) played so far, in order.
+    #[serde(default)]
+    history: Vec<(usize, Action)>,
+
+    // Profiling counters. They aren't saved, and clones carry on from the
+    // original's counts.
+    #[cfg(feature = "metrics")]
+    #[serde(skip)]
+    metrics: Cell<Metrics>,
 }
 impl GameState {
     pub fn init(num_players: usize) -> Result<GameState, DynError> {
-        if !(2..=9).contains(&num_players) {
-            return Err("Invalid number of players".into());
-        }
+        Self::init_seeded(num_players, rand::random())
+    }
+    pub fn init_seeded(num_players: usize, seed: u64) -> Result<GameState, DynError> {
         let cards = load_from_csv::<Card>(include_str!("../cards.csv"))?;
+        let nobles = load_from_csv::<Noble>(include_str!("../nobles.csv"))?;
+        Self::init_with_deck(num_players, seed, cards, nobles)
+    }
+    // Starts a game with a custom deck of cards and nobles. If the cards (or
+    // nobles) have no ids, they're numbered from 1 in the order given.
+    pub fn init_with_deck(
+        num_players: usize,
+        seed: u64,
+        mut cards: Vec<Card>,
+        mut nobles: Vec<Noble>,
+    ) -> Result<GameState, DynError> {
+        if !(2..=MAX_PLAYERS).contains(&num_players) {
+            return Err(format!(
+                "Invalid number of players: {}, must be 2 to {}",
+                num_players, MAX_PLAYERS
+            )
+            .into());
+        }
+        validate_nobles(&nobles, &cards)?;
+        if cards.iter().all(|c| c.id == 0) {
+            for (id, card) in (1..).zip(cards.iter_mut()) {
+                card.id = id;
+            }
+        }
+        if nobles.iter().all(|n| n.id == 0) {
+            for (id, noble) in (1..).zip(nobles.iter_mut()) {
+                noble.id = id;
+            }
+        }
         let mut market = [Vec::new(), Vec::new(), Vec::new()];
         for card in cards {
+            if !(1..=3).contains(&card.level) {
+                return Err(format!("Card has invalid level: {}", card.level).into());
+            }
             market[card.level - 1].push(card);
         }
-        let mut rng = rand::thread_rng();
+        // Each market row starts with 4 face-up cards.
+        for (level, cards) in market.iter().enumerate() {
+            if cards.len() < 4 {
+                return Err(format!(
+                    "Deck has only {} level {} cards, but at least 4 are needed",
+                    cards.len(),
+                    level + 1
+                )
+                .into());
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
         market[0].shuffle(&mut rng);
         market[1].shuffle(&mut rng);
         market[2].shuffle(&mut rng);
@@ -48,7 +155,6 @@ impl GameState {
             market[2].split_off(4),
         ];
 
-        let mut nobles = load_from_csv::<Noble>(include_str!("../nobles.csv"))?;
         nobles.shuffle(&mut rng);
         nobles.truncate(num_players + 1);
 
@@ -67,116 +173,954 @@ impl GameState {
             bank,
             players: (0..num_players).map(|_| Player::default()).collect(),
             curr_player_idx,
+            first_player: curr_player_idx,
             round: 1,
+            turns: 0,
+            passes: 0,
+            finished: false,
+            outcome: None,
+            ruleset: Ruleset::standard(),
+            seed: Some(seed),
+            pending: None,
+            history: Vec::new(),
+            #[cfg(feature = "metrics")]
+            metrics: Cell::default(),
         })
     }
+    // The seed this game was dealt from, which init_seeded can use to deal
+    // an identical game. None for games rebuilt from partial information.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    // Re-deals a seeded game and re-applies the given moves, checking that
+    // each was played by the player whose turn it was.
+    pub fn replay(
+        num_players: usize,
+        seed: u64,
+        actions: &[(usize, Action)],
+    ) -> Result<GameState, DynError> {
+        let mut gs = Self::init_seeded(num_players, seed)?;
+        for (turn, (idx, action)) in actions.iter().enumerate() {
+            if gs.curr_player_idx != *idx {
+                return Err(format!(
+                    "turn {}: expected player {} to act, but it was player {}",
+                    turn, idx, gs.curr_player_idx
+                )
+                .into());
+            }
+            gs.take_turn(action)
+                .map_err(|e| format!("turn {}: {:?} was rejected: {}", turn, action, e))?;
+        }
+        Ok(gs)
+    }
+    pub fn history(&self) -> &[(usize, Action)] {
+        &self.history
+    }
+    pub fn init_with_config(config: &GameConfig) -> Result<GameState, DynError> {
+        if config.target_vp == 0 {
+            return Err("Target VP must be positive".into());
+        }
+        let seed = config.seed.unwrap_or_else(rand::random);
+        let mut gs = Self::init_seeded(config.num_players, seed)?;
+        if let Some(bank) = config.bank {
+            if bank
+                .iter()
+                .zip(GameConfig::MAX_BANK)
+                .any(|(&n, max)| n > max)
+            {
+                return Err(format!(
+                    "Bank {:?} exceeds the tokens available: {:?}",
+                    bank,
+                    GameConfig::MAX_BANK
+                )
+                .into());
+            }
+            gs.bank = bank;
+        }
+        Ok(gs.with_ruleset(Ruleset {
+            target_vp: config.target_vp,
+            ..Ruleset::standard()
+        }))
+    }
+    pub fn with_ruleset(mut self, ruleset: Ruleset) -> Self {
+        self.ruleset = ruleset;
+        self
+    }
+    pub fn ruleset(&self) -> &Ruleset {
+        &self.ruleset
+    }
+    pub fn target_vp(&self) -> u8 {
+        self.ruleset.target_vp
+    }
     pub fn curr_player(&self) -> &Player {
         &self.players[self.curr_player_idx]
     }
-    pub fn take_turn(&mut self, action: &Action) -> Result<bool, DynError> {
-        let old_vp = self.curr_player().vp();
-        let mut new_vp = old_vp;
+    // Each player's per-color discounts from owned cards, in seat order.
+    pub fn player_discounts(&self) -> Vec<[u8; 5]> {
+        self.players.iter().map(|p| p.discounts()).collect()
+    }
+    // Scalar reads for scoreboards. None if there is no such player.
+    pub fn player_vp(&self, idx: usize) -> Option<u8> {
+        self.players.get(idx).map(|p| p.vp())
+    }
+    pub fn player_token_count(&self, idx: usize) -> Option<u8> {
+        self.players.get(idx).map(|p| p.num_tokens())
+    }
+    // Every player's public standing, in seat order.
+    pub fn scoreboard(&self) -> Vec<PlayerSummary> {
+        self.players
+            .iter()
+            .map(|p| PlayerSummary {
+                vp: p.vp(),
+                tokens: p.tokens,
+                cards: p.discounts(),
+                nobles: p.nobles.len(),
+                reserved: p.num_reserved(),
+            })
+            .collect()
+    }
+    // Total VP all players have earned from nobles.
+    pub fn noble_vp_total(&self) -> u8 {
+        self.players.iter().map(|p| p.noble_vp()).sum()
+    }
+    // Updates the current player's VP history, if they gained VP.
+    fn record_vp(&mut self, old_vp: u8, new_vp: u8) {
+        if new_vp > old_vp {
+            self.players[self.curr_player_idx]
+                .vp_history
+                .push((self.round, new_vp));
+        }
+    }
+    // Checks whether the current player may take this action, without
+    // applying it.
+    pub fn validate_action(&self, action: &Action) -> Result<(), ActionError> {
+        if self.is_finished() {
+            return Err(ActionError::GameOver);
+        }
+        match (self.pending, action) {
+            (Some(PendingChoice::ReturnTokens(_)), Action::ReturnTokens(_)) => {}
+            (Some(PendingChoice::ReturnTokens(_)), _) => return Err(ActionError::MustReturnTokens),
+            (Some(PendingChoice::AcquireNoble), Action::AcquireNoble(_)) => {}
+            (Some(PendingChoice::AcquireNoble), _) => return Err(ActionError::MustChooseNoble),
+            (None, Action::ReturnTokens(_)) => return Err(ActionError::NothingToReturn),
+            (None, Action::AcquireNoble(_)) => return Err(ActionError::NoNobleToChoose),
+            (None, _) => {}
+        }
+        let player = self.curr_player();
         match action {
             Action::TakeDifferentColorTokens(colors) => {
                 if colors.len() > 3 {
-                    return Err("Cannot take more than 3 tokens".into());
+                    return Err(ActionError::TooManyTokens);
                 }
                 for (i, &c) in colors.iter().enumerate() {
                     if c == Color::Gold {
-                        return Err("Cannot take a gold token".into());
+                        return Err(ActionError::GoldNotTakeable);
                     }
                     if self.bank[c as usize] == 0 {
-                        return Err("Not enough tokens in bank".into());
+                        return Err(ActionError::InsufficientBank);
                     }
                     if colors[i + 1..].contains(&c) {
-                        return Err("Cannot take the same color twice".into());
-                    }
-                }
-                if self.curr_player().num_tokens() as usize + colors.len() > 10 {
-                    return Err("Cannot take more than 10 tokens".into());
-                }
-                let player = &mut self.players[self.curr_player_idx];
-                for &color in colors {
-                    let c = color as usize;
-                    if self.bank[c] > 0 {
-                        self.bank[c] -= 1;
-                        player.tokens[c] += 1;
+                        return Err(ActionError::DuplicateColor);
                     }
                 }
             }
             Action::TakeSameColorTokens(color) => {
                 if color == &Color::Gold {
-                    return Err("Cannot take a gold token".into());
-                }
-                let c = *color as usize;
-                if self.bank[c] < 4 {
-                    return Err("Not enough tokens in bank".into());
+                    return Err(ActionError::GoldNotTakeable);
                 }
-                if self.curr_player().num_tokens() + 2 > 10 {
-                    return Err("Cannot take more than 10 tokens".into());
+                if !self.can_take_same(*color) {
+                    return Err(ActionError::InsufficientBank);
                 }
-                self.bank[c] -= 2;
-                self.players[self.curr_player_idx].tokens[c] += 2;
             }
             Action::ReserveCard(loc) => {
                 if let CardLocation::Reserve(_) = loc {
-                    return Err("Card is already reserved".into());
+                    return Err(ActionError::AlreadyReserved);
                 }
-                if !self.curr_player().can_reserve() {
-                    return Err("At most 3 cards can be reserved".into());
+                if !player.can_reserve() {
+                    return Err(ActionError::ReserveFull);
                 }
-                let card = self.take_card(loc)?;
-                self.players[self.curr_player_idx].reserve(card, &mut self.bank[5]);
+                self.check_location(loc)?;
             }
             Action::BuyCard(loc) => {
-                if !self.curr_player().can_buy(self.peek_card(loc)?) {
-                    return Err("Cannot afford card".into());
+                if let CardLocation::Pile(_) = loc {
+                    return Err(ActionError::BuyFromPile);
+                }
+                self.check_location(loc)?;
+                if !player.can_buy(self.peek_card(loc).unwrap()) {
+                    return Err(ActionError::CannotAfford);
+                }
+            }
+            Action::BuyCardWithPayment(loc, payment) => {
+                if let CardLocation::Pile(_) = loc {
+                    return Err(ActionError::BuyFromPile);
+                }
+                self.check_location(loc)?;
+                if !player.is_valid_payment(self.peek_card(loc).unwrap(), payment) {
+                    return Err(ActionError::InvalidPayment);
+                }
+            }
+            Action::Pass => {}
+            Action::ReturnTokens(colors) => {
+                let Some(PendingChoice::ReturnTokens(n)) = self.pending else {
+                    unreachable!()
+                };
+                if colors.len() != n as usize {
+                    return Err(ActionError::WrongReturnCount(n));
+                }
+                let mut counts = [0u8; 6];
+                for &c in colors {
+                    counts[c as usize] += 1;
+                }
+                if counts
+                    .iter()
+                    .zip(player.tokens.iter())
+                    .any(|(n, have)| n > have)
+                {
+                    return Err(ActionError::ReturnNotHeld);
+                }
+            }
+            Action::AcquireNoble(idx) => {
+                if !player.acquirable_nobles(&self.nobles).contains(idx) {
+                    return Err(ActionError::NobleUnavailable);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn check_location(&self, loc: &CardLocation) -> Result<(), ActionError> {
+        match loc {
+            CardLocation::Pile(level) => {
+                if !(1..=3).contains(level) {
+                    return Err(ActionError::InvalidLocation("Invalid pile level".into()));
+                }
+                if self.piles[*level - 1].is_empty() {
+                    return Err(ActionError::InvalidLocation("No cards left".into()));
+                }
+                Ok(())
+            }
+            _ => self
+                .peek_card(loc)
+                .map(|_| ())
+                .map_err(|e| ActionError::InvalidLocation(e.to_string())),
+        }
+    }
+    pub fn take_turn(&mut self, action: &Action) -> Result<TurnOutcome, DynError> {
+        // Older clients pass by taking zero tokens, so count that as a pass.
+        let action = match action {
+            Action::TakeDifferentColorTokens(colors) if colors.is_empty() => &Action::Pass,
+            _ => action,
+        };
+        self.validate_action(action)?;
+        let player_idx = self.curr_player_idx;
+        let num_nobles = self.players[player_idx].nobles.len();
+        let old_vp = self.players[player_idx].vp();
+        let bought = match action {
+            Action::BuyCard(loc) | Action::BuyCardWithPayment(loc, _) => {
+                self.peek_card(loc).ok().cloned()
+            }
+            _ => None,
+        };
+        let (is_over, gold_granted) = self.apply_action(action)?;
+        let player = &self.players[player_idx];
+        let card = match action {
+            Action::ReserveCard(_) => player.last_reserved().cloned(),
+            _ => bought,
+        };
+        Ok(TurnOutcome {
+            is_over,
+            noble: player.nobles.get(num_nobles).cloned(),
+            vp_gained: player.vp() - old_vp,
+            card,
+            gold_granted,
+        })
+    }
+    // Applies a validated action, returning whether the game is now over and
+    // whether a reserve granted a gold token.
+    fn apply_action(&mut self, action: &Action) -> Result<(bool, bool), DynError> {
+        let old_vp = self.curr_player().vp();
+        let mut new_vp = old_vp;
+        let mut gold_granted = false;
+        match action {
+            Action::TakeDifferentColorTokens(colors) => {
+                let player = &mut self.players[self.curr_player_idx];
+                for &color in colors {
+                    player.add_tokens(color, 1, &mut self.bank);
                 }
+            }
+            Action::TakeSameColorTokens(color) => {
+                self.players[self.curr_player_idx].add_tokens(*color, 2, &mut self.bank);
+            }
+            Action::ReserveCard(loc) => {
+                let card = self.take_card(loc)?;
+                let max_tokens = self.ruleset.max_tokens;
+                let player = &mut self.players[self.curr_player_idx];
+                gold_granted = if matches!(loc, CardLocation::Pile(_)) {
+                    player.reserve_blind(card, &mut self.bank[5], max_tokens)
+                } else {
+                    player.reserve(card, &mut self.bank[5], max_tokens)
+                };
+            }
+            Action::BuyCard(loc) => {
                 let card = self.take_card(loc)?;
                 new_vp += card.vp;
                 self.players[self.curr_player_idx].buy(card, &mut self.bank);
             }
+            Action::BuyCardWithPayment(loc, payment) => {
+                let card = self.take_card(loc)?;
+                new_vp += card.vp;
+                self.players[self.curr_player_idx].buy_with_payment(
+                    card,
+                    *payment,
+                    &mut self.bank,
+                )?;
+            }
+            Action::Pass => {}
+            Action::ReturnTokens(colors) => {
+                let player = &mut self.players[self.curr_player_idx];
+                for &c in colors {
+                    player.remove_tokens(c, 1, &mut self.bank);
+                }
+                self.pending = None;
+            }
+            Action::AcquireNoble(idx) => {
+                new_vp += self.players[self.curr_player_idx]
+                    .acquire_noble(*idx, &mut self.nobles)
+                    .ok_or(ActionError::NobleUnavailable)?;
+                self.pending = None;
+            }
+        }
+        self.history.push((self.curr_player_idx, action.clone()));
+        // Only forced passes count towards a stalemate, since a player who
+        // passes by choice could still move later.
+        if *action == Action::Pass && self.legal_actions_for(self.curr_player_idx) == [Action::Pass]
+        {
+            self.passes += 1;
+        } else {
+            self.passes = 0;
+        }
+        // Taking tokens past the limit is allowed, but the turn doesn't end
+        // until the player returns the excess.
+        let num_tokens = self.curr_player().num_tokens();
+        let max_tokens = self.ruleset.max_tokens;
+        if num_tokens > max_tokens
+            && matches!(
+                action,
+                Action::TakeDifferentColorTokens(_) | Action::TakeSameColorTokens(_)
+            )
+        {
+            self.pending = Some(PendingChoice::ReturnTokens(num_tokens - max_tokens));
+            return Ok((false, gold_granted));
         }
-        // If a player can acquire a noble, they do so.
+        // If a player can acquire a noble, they do so. When several qualify,
+        // the player chooses one with a follow-up action.
         // At most one noble can be acquired per player per round.
-        new_vp += self.players[self.curr_player_idx].acquire_best_noble(&mut self.nobles);
-        // Update the player's VP history, if they gained VP.
-        if new_vp > old_vp {
-            self.players[self.curr_player_idx]
-                .vp_history
-                .push((self.round, new_vp));
+        if !matches!(action, Action::AcquireNoble(_)) {
+            let player = &self.players[self.curr_player_idx];
+            if player.acquirable_nobles(&self.nobles).len() > 1 {
+                self.record_vp(old_vp, new_vp);
+                self.pending = Some(PendingChoice::AcquireNoble);
+                return Ok((false, gold_granted));
+            }
+            new_vp += self.players[self.curr_player_idx].acquire_best_noble(&mut self.nobles);
+        }
+        self.record_vp(old_vp, new_vp);
+        self.ensure_market_full();
+        self.turns += 1;
+        // If everyone has passed since anything changed, nobody can move
+        // again.
+        if self.passes >= self.players.len() {
+            self.outcome = Some(GameOutcome::Stalemate);
+            self.finished = true;
+            return Ok((true, gold_granted));
         }
         // Advance to the next player.
-        self.curr_player_idx += 1;
-        // If the round is over, check if the game is over too.
-        if self.curr_player_idx == self.players.len() {
-            // If any player has 15+ VP, the game is over.
-            if self.players.iter().any(|p| p.vp() >= 15) {
-                return Ok(true);
+        self.curr_player_idx = (self.curr_player_idx + 1) % self.players.len();
+        // If the round is over, check if the game is over too. Every player
+        // has then had the same number of turns.
+        if self.curr_player_idx == self.first_player {
+            // If any player has reached the VP target, the game is over.
+            if self.players.iter().any(|p| p.vp() >= self.target_vp()) {
+                self.outcome = Some(GameOutcome::VictoryThreshold);
+                self.finished = true;
+                return Ok((true, gold_granted));
             }
             self.round += 1;
-            self.curr_player_idx = 0;
         }
-        Ok(false)
+        Ok((false, gold_granted))
+    }
+    // Like take_turn, but reports what happened to on_event: the turn itself,
+    // then any noble it earned, then the end of the game if it ended.
+    pub fn process_action_events(
+        &mut self,
+        action: &Action,
+        on_event: &mut impl FnMut(&GameEvent),
+    ) -> Result<bool, DynError> {
+        let player_idx = self.curr_player_idx;
+        let outcome = self.take_turn(action)?;
+        on_event(&GameEvent::TurnTaken {
+            player_idx,
+            action: action.clone(),
+            new_scores: self.players.iter().map(|p| p.vp()).collect(),
+            gold_granted: outcome.gold_granted,
+        });
+        if let Some(noble) = &outcome.noble {
+            on_event(&GameEvent::NobleAcquired {
+                player_idx,
+                noble: noble.clone(),
+            });
+        }
+        if outcome.is_over {
+            on_event(&GameEvent::GameOver {
+                winners: self.winners(),
+            });
+        }
+        Ok(outcome.is_over)
+    }
+    // Plays consecutive turns for seats that have an agent, stopping when a
+    // seat without an agent (i.e. a human) is up, or when the game ends.
+    // on_turn sees each (player index, action, outcome) as it's played.
+    // Returns the (player index, action) pairs that were played. If the
+    // agents alone run past the turn limit, the game ends with a Timeout.
+    pub fn fast_forward(
+        &mut self,
+        agents: &[Option<Box<dyn Agent + Send>>],
+        on_turn: &mut impl FnMut(usize, &Action, &TurnOutcome),
+    ) -> Result<Vec<(usize, Action)>, DynError> {
+        let mut played = Vec::new();
+        while !self.is_finished() {
+            let idx = self.curr_player_idx;
+            let Some(agent) = agents.get(idx).and_then(|a| a.as_ref()) else {
+                break;
+            };
+            if played.len() >= MAX_SIMULATED_TURNS {
+                return Err(self.time_out());
+            }
+            let action = agent.choose_action(self);
+            let outcome = self.take_turn(&action)?;
+            on_turn(idx, &action, &outcome);
+            played.push((idx, action));
+        }
+        Ok(played)
+    }
+    // Drives a game from a script of JSON-encoded actions for the human seats,
+    // letting agents play in between. Returns every (player index, action)
+    // pair played, in order.
+    pub fn play_script(
+        &mut self,
+        agents: &[Option<Box<dyn Agent + Send>>],
+        script: &[&str],
+    ) -> Result<Vec<(usize, Action)>, DynError> {
+        let mut played = self.fast_forward(agents, &mut |_, _, _| {})?;
+        for json in script {
+            let action: Action = serde_json::from_str(json)?;
+            let idx = self.curr_player_idx;
+            self.take_turn(&action)?;
+            played.push((idx, action));
+            played.extend(self.fast_forward(agents, &mut |_, _, _| {})?);
+        }
+        Ok(played)
+    }
+    // Plays out the rest of the game using the same agent for every seat,
+    // returning the final VP of each player. A game that hits the turn limit
+    // ends there with a Timeout, and the error says so.
+    pub fn simulate_to_end_with(&mut self, agent: &dyn Agent) -> Result<Vec<u8>, DynError> {
+        self.simulate_within(agent, MAX_SIMULATED_TURNS)
+    }
+    fn simulate_within(
+        &mut self,
+        agent: &dyn Agent,
+        max_turns: usize,
+    ) -> Result<Vec<u8>, DynError> {
+        for _turn in 0..max_turns {
+            if self.is_finished() {
+                break;
+            }
+            let action = agent.choose_action(self);
+            self.take_turn(&action)?;
+        }
+        if self.is_finished() {
+            return Ok(self.players.iter().map(|p| p.vp()).collect());
+        }
+        Err(self.time_out())
+    }
+    // Ends a game that ran past the turn limit, returning the error to report.
+    fn time_out(&mut self) -> DynError {
+        self.outcome = Some(GameOutcome::Timeout);
+        self.finished = true;
+        self.pending = None;
+        "Game did not finish within the turn limit".into()
+    }
+    // A greedy plan of cards (from the market and the current player's
+    // reserve) to buy to complete the given noble: colors with the most cards
+    // still needed come first, and the cheapest cards within each color.
+    // Returns None if the visible cards can't cover what's needed.
+    pub fn tokens_needed_for_noble_race(&self, noble: &Noble) -> Option<Vec<CardLocation>> {
+        let player = self.curr_player();
+        let needed = player.noble_progress(noble);
+        let mut candidates: Vec<(CardLocation, &Card)> = Vec::new();
+        for (level, row) in self.market.iter().enumerate() {
+            for (idx, card) in row.iter().enumerate() {
+                candidates.push((CardLocation::Market(level + 1, idx), card));
+            }
+        }
+        for idx in 0..3 {
+            if let Some(card) = player.peek_reserved(idx) {
+                candidates.push((CardLocation::Reserve(idx), card));
+            }
+        }
+        // Cheapest means fewest tokens still to collect, then fewest to spend.
+        let cost = |card: &Card| {
+            let spent: u8 = player.tokens_returned_by_buy(card).iter().sum();
+            (player.affordability(card).shortfall, spent)
+        };
+        let mut colors: Vec<usize> = (0..5).filter(|&c| needed[c] > 0).collect();
+        colors.sort_by_key(|&c| std::cmp::Reverse(needed[c]));
+        let mut plan = Vec::new();
+        for c in colors {
+            let mut of_color: Vec<&(CardLocation, &Card)> = candidates
+                .iter()
+                .filter(|(_, card)| card.color as usize == c)
+                .collect();
+            if of_color.len() < needed[c] as usize {
+                return None;
+            }
+            of_color.sort_by_key(|(_, card)| cost(card));
+            plan.extend(
+                of_color
+                    .iter()
+                    .take(needed[c] as usize)
+                    .map(|(loc, _)| loc.clone()),
+            );
+        }
+        Some(plan)
+    }
+    // Describes what changed since `prev`, one line per change: tokens taken
+    // or paid, cards bought or reserved, nobles claimed, and market refills.
+    pub fn render_diff(&self, prev: &GameState) -> String {
+        let tokens_string = |counts: &[u8; 6]| {
+            counts
+                .iter()
+                .enumerate()
+                .filter(|(_, &n)| n > 0)
+                .map(|(i, n)| format!("{}{}", n, Color::try_from(i).unwrap().symbol()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let reserved = |p: &Player| (0..3).filter_map(|i| p.peek_reserved(i).cloned()).collect();
+        let mut lines = Vec::new();
+        for (i, (now, before)) in self.players.iter().zip(prev.players.iter()).enumerate() {
+            let (mut gained, mut lost) = ([0u8; 6], [0u8; 6]);
+            for c in 0..6 {
+                gained[c] = now.tokens[c].saturating_sub(before.tokens[c]);
+                lost[c] = before.tokens[c].saturating_sub(now.tokens[c]);
+            }
+            if gained.iter().any(|&n| n > 0) {
+                lines.push(format!("Player {} took {}", i, tokens_string(&gained)));
+            }
+            if lost.iter().any(|&n| n > 0) {
+                lines.push(format!("Player {} paid {}", i, tokens_string(&lost)));
+            }
+            let (now_reserved, before_reserved): (Vec<Card>, Vec<Card>) =
+                (reserved(now), reserved(before));
+            for card in now_reserved.iter().filter(|c| !before_reserved.contains(c)) {
+                lines.push(format!("Player {} reserved {}", i, card));
+            }
+            if now.num_cards() > before.num_cards() {
+                // The bought card left either the market or this player's reserve.
+                let bought = prev
+                    .market
+                    .iter()
+                    .flatten()
+                    .filter(|c| !self.market.iter().flatten().any(|m| m == *c))
+                    .chain(before_reserved.iter())
+                    .find(|c| !now_reserved.contains(c));
+                match bought {
+                    Some(card) => lines.push(format!("Player {} bought {}", i, card)),
+                    None => lines.push(format!("Player {} bought a card", i)),
+                }
+            }
+            for noble in now.nobles.iter().skip(before.nobles.len()) {
+                lines.push(format!("Player {} claimed {}", i, noble));
+            }
+        }
+        for card in self.market.iter().flatten() {
+            if !prev.market.iter().flatten().any(|c| c == card) {
+                lines.push(format!("New in market: {}", card));
+            }
+        }
+        lines.join("\n")
+    }
+    // For each available noble, the number of players who are at most one
+    // card away from acquiring it.
+    pub fn noble_competition_pressure(&self) -> Vec<usize> {
+        self.nobles
+            .iter()
+            .map(|n| {
+                self.players
+                    .iter()
+                    .filter(|p| p.noble_progress(n).iter().sum::<u8>() <= 1)
+                    .count()
+            })
+            .collect()
+    }
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        self.outcome
+    }
+    pub fn round(&self) -> u16 {
+        self.round
+    }
+    pub fn turn_count(&self) -> u32 {
+        self.turns
+    }
+    // Indices of the winning players once the game is over (empty before
+    // then): most VP, with ties going to the player with the fewest purchased
+    // (development) cards. Nobles are not cards, so holding more nobles
+    // neither helps nor hurts in a tie-break. If still tied, the win is shared.
+    pub fn winners(&self) -> Vec<usize> {
+        if !self.is_finished() {
+            return Vec::new();
+        }
+        let key = |p: &Player| (p.vp(), std::cmp::Reverse(p.num_cards()));
+        let Some(best) = self.players.iter().map(key).max() else {
+            return Vec::new();
+        };
+        (0..self.players.len())
+            .filter(|&i| key(&self.players[i]) == best)
+            .collect()
+    }
+    // The earliest seat among the winners, for callers that need just one.
+    pub fn winner(&self) -> Option<usize> {
+        self.winners().first().copied()
+    }
+    pub fn first_player(&self) -> usize {
+        self.first_player
+    }
+    // True once some player has reached the VP target, meaning the current
+    // round is the last one.
+    pub fn is_last_round(&self) -> bool {
+        !self.is_finished() && self.players.iter().any(|p| p.vp() >= self.target_vp())
+    }
+    // Total tokens of each color in the game, whether in the bank or held.
+    pub fn token_supply_total(&self) -> [u8; 6] {
+        let mut total = self.bank;
+        for p in self.players.iter() {
+            for (t, n) in total.iter_mut().zip(p.tokens.iter()) {
+                *t += n;
+            }
+        }
+        total
+    }
+    // Fraction of each (non-gold) color's supply currently held by players.
+    pub fn token_pressure(&self) -> [f32; 5] {
+        let total = self.token_supply_total();
+        let mut pressure = [0.0; 5];
+        for (i, p) in pressure.iter_mut().enumerate() {
+            if total[i] > 0 {
+                *p = 1.0 - self.bank[i] as f32 / total[i] as f32;
+            }
+        }
+        pressure
+    }
+    // Hash of the public board (market, nobles, bank), ignoring players.
+    // It's FNV-1a over the card and noble ids and the bank, so it's the same
+    // across builds and toolchains, and can key an opening book on disk.
+    pub fn hash_market_signature(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for &b in bytes {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for row in self.market.iter() {
+            feed(&[row.len() as u8]);
+            for card in row {
+                feed(&card.id.to_le_bytes());
+            }
+        }
+        feed(&[self.nobles.len() as u8]);
+        for noble in self.nobles.iter() {
+            feed(&noble.id.to_le_bytes());
+        }
+        feed(&self.bank);
+        hash
+    }
+    // Plays a uniformly random legal action, returning whether the game is now
+    // over along with the action that was played.
+    pub fn apply_random_action(&mut self, rng: &mut impl Rng) -> Result<(bool, Action), DynError> {
+        let action = self
+            .valid_actions()
+            .choose(rng)
+            .cloned()
+            .ok_or("No valid actions")?;
+        let is_over = self.take_turn(&action)?.is_over;
+        Ok((is_over, action))
+    }
+    // Invariant: each market row is refilled from its pile whenever possible,
+    // so it holds min(4, cards remaining at that level) cards.
+    fn ensure_market_full(&self) {
+        for (level, (row, pile)) in self.market.iter().zip(self.piles.iter()).enumerate() {
+            debug_assert_eq!(
+                row.len(),
+                (row.len() + pile.len()).min(4),
+                "Market row {} was not refilled",
+                level + 1
+            );
+        }
     }
     pub fn is_finished(&self) -> bool {
-        self.curr_player_idx >= self.players.len()
+        // Older serialized games marked the end by moving curr_player_idx past
+        // the last seat instead.
+        self.finished || self.curr_player_idx >= self.players.len()
     }
     pub fn peek_card(&self, loc: &CardLocation) -> Result<&Card, DynError> {
         match loc {
             CardLocation::Pile(_) => Err("No peeking at the pile".into()),
-            CardLocation::Market(level, idx) => self
-                .market
-                .get(*level - 1)
-                .ok_or("Invalid market level")?
-                .get(*idx)
-                .ok_or_else(|| "Invalid market index".into()),
+            CardLocation::Market(level, idx) => {
+                if !(1..=3).contains(level) {
+                    return Err("Invalid market level".into());
+                }
+                self.market[*level - 1]
+                    .get(*idx)
+                    .ok_or_else(|| "Invalid market index".into())
+            }
             CardLocation::Reserve(idx) => self
                 .curr_player()
                 .peek_reserved(*idx)
                 .ok_or_else(|| "Invalid reserve index".into()),
         }
     }
+    // The card that would refill the given market slot if it were taken.
+    // This reveals hidden information, so it must only be used for
+    // debugging/admin purposes (e.g. animation hints), never for players.
+    pub fn market_refill_preview(&self, loc: &CardLocation) -> Option<&Card> {
+        match loc {
+            CardLocation::Market(level, idx) if (1..=3).contains(level) => {
+                if *idx >= self.market[*level - 1].len() {
+                    return None;
+                }
+                self.piles[*level - 1].last()
+            }
+            _ => None,
+        }
+    }
+    // The highest-VP card the current player can afford right now, from the
+    // market or their reserve. Ties go to the first card found.
+    pub fn max_affordable_card(&self) -> Option<(CardLocation, &Card)> {
+        let player = self.curr_player();
+        let market = self.market.iter().enumerate().flat_map(|(level, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(idx, card)| (CardLocation::Market(level + 1, idx), card))
+        });
+        let reserved = player
+            .buyable_reserved_cards()
+            .into_iter()
+            .filter_map(|idx| Some((CardLocation::Reserve(idx), player.peek_reserved(idx)?)));
+        let mut best: Option<(CardLocation, &Card)> = None;
+        for (loc, card) in market.filter(|(_, c)| player.can_buy(c)).chain(reserved) {
+            if best.as_ref().is_none_or(|(_, b)| card.vp > b.vp) {
+                best = Some((loc, card));
+            }
+        }
+        best
+    }
+    // Heuristic value of the position for one player, in rough VP units, for
+    // agents and external evaluators. The components are:
+    //  - VP lead: the player's VP minus the best opponent's
+    //  - production: 0.25 per owned card, since each is a permanent discount
+    //  - noble proximity: the best, over the nobles on the table, of its VP
+    //    times the fraction of its cost the player's cards already cover
+    //  - reserves: 0.1 per VP on a reserved card
+    //  - tokens: 0.05 per colored token and 0.1 per gold token
+    pub fn evaluate(&self, player_idx: usize) -> f32 {
+        let player = &self.players[player_idx];
+        let best_opponent = self
+            .players
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != player_idx)
+            .map(|(_, p)| p.vp())
+            .max()
+            .unwrap_or(0);
+        let vp_lead = player.vp() as f32 - best_opponent as f32;
+        let discounts = player.discounts();
+        let noble_proximity = self
+            .nobles
+            .iter()
+            .map(|n| {
+                let total: u8 = n.cost.iter().sum();
+                let covered: u8 = n.cost.iter().zip(discounts).map(|(&c, d)| c.min(d)).sum();
+                n.vp as f32 * covered as f32 / total.max(1) as f32
+            })
+            .fold(0.0, f32::max);
+        let reserved_vp: u8 = (0..)
+            .map_while(|i| player.peek_reserved(i))
+            .map(|c| c.vp)
+            .sum();
+        let colored_tokens: u8 = player.tokens[..5].iter().sum();
+        vp_lead
+            + 0.25 * player.num_cards() as f32
+            + noble_proximity
+            + 0.1 * reserved_vp as f32
+            + 0.05 * colored_tokens as f32
+            + 0.1 * player.tokens[5] as f32
+    }
+    // Gives the first turn to the given player, as if the deal had.
+    pub(crate) fn start_with(&mut self, player_idx: usize) {
+        self.curr_player_idx = player_idx;
+        self.first_player = player_idx;
+    }
+    // Replaces the pile at the given level so that market refills (and blind
+    // reserves) draw exactly these cards, in order. Intended for scenario tests.
+    pub fn stack_pile(&mut self, level: usize, draws: Vec<Card>) -> Result<(), DynError> {
+        if !(1..=3).contains(&level) {
+            return Err("Invalid pile level".into());
+        }
+        self.piles[level - 1] = draws.into_iter().rev().collect();
+        Ok(())
+    }
+    // The states an action can lead to, with their probabilities. Taking a
+    // card from the market or a pile draws an unknown card from that pile. As
+    // far as the current player knows, it could be any card they haven't seen:
+    // one left in the pile, or one an opponent holds face down (see
+    // redacted_for). Each distinct unseen card is a separate outcome.
+    pub fn outcomes(&self, action: &Action) -> Result<Vec<(f64, GameState)>, DynError> {
+        let level = match action {
+            Action::ReserveCard(CardLocation::Market(level, _) | CardLocation::Pile(level))
+            | Action::BuyCard(CardLocation::Market(level, _))
+            | Action::BuyCardWithPayment(CardLocation::Market(level, _), _)
+                if (1..=3).contains(level) =>
+            {
+                Some(*level - 1)
+            }
+            _ => None,
+        };
+        let Some(level) = level.filter(|&level| !self.piles[level].is_empty()) else {
+            let mut next = self.clone();
+            next.take_turn(action)?;
+            return Ok(vec![(1.0, next)]);
+        };
+        // Each unseen card, with where it is: None for the pile, or the
+        // opponent and reserve index holding it.
+        let mut unseen: Vec<(&Card, Option<(usize, usize)>)> =
+            self.piles[level].iter().map(|c| (c, None)).collect();
+        for (i, player) in self.players.iter().enumerate() {
+            if i == self.curr_player_idx {
+                continue;
+            }
+            // Only blind reserves are unseen; market ones were face up.
+            for (j, card) in (0..).map_while(|j| player.peek_reserved(j)).enumerate() {
+                if card.level == level + 1 && player.is_blind_reserved(j) {
+                    unseen.push((card, Some((i, j))));
+                }
+            }
+        }
+        let mut counts = Vec::new();
+        for &(card, place) in unseen.iter() {
+            match counts.iter_mut().find(|(c, _, _)| *c == card) {
+                Some((_, _, n)) => *n += 1,
+                None => counts.push((card, place, 1)),
+            }
+        }
+        let mut outcomes = Vec::with_capacity(counts.len());
+        for (card, place, n) in counts {
+            let mut next = self.clone();
+            // Move a copy of this card to the top of the pile, so it's drawn.
+            let pile = &mut next.piles[level];
+            let last = pile.len() - 1;
+            match place {
+                None => {
+                    let pos = pile.iter().rposition(|c| c == card).unwrap();
+                    pile.swap(pos, last);
+                }
+                // The opponent holds the old top card instead.
+                Some((i, j)) => {
+                    let top = std::mem::replace(&mut pile[last], card.clone());
+                    next.players[i].replace_reserved(j, top);
+                }
+            }
+            next.take_turn(action)?;
+            outcomes.push((n as f64 / unseen.len() as f64, next));
+        }
+        Ok(outcomes)
+    }
+    // The game as the given player sees it: opponents' blind reserves are
+    // face down, and the piles are empty. The seed is dropped too, since
+    // redealing from it would reveal the piles.
+    pub fn redacted_for(&self, player_idx: usize) -> GameState {
+        let mut view = self.clone();
+        view.piles = [Vec::new(), Vec::new(), Vec::new()];
+        view.seed = None;
+        for (i, player) in view.players.iter_mut().enumerate() {
+            if i != player_idx {
+                player.hide_reserved();
+            }
+        }
+        view
+    }
+    // Number of face-down cards left in each pile. The real game shows how
+    // thick each deck is, so this is public even though the cards aren't.
+    pub fn pile_counts(&self) -> [usize; 3] {
+        [
+            self.piles[0].len(),
+            self.piles[1].len(),
+            self.piles[2].len(),
+        ]
+    }
+    // Compact binary form of the whole game, piles included, for storage.
+    // Integers are varint-encoded, which is what makes it small. Unlike JSON,
+    // it can't be read back by a different version of the engine.
+    #[cfg(feature = "bincode")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DynError> {
+        Ok(bincode::DefaultOptions::new().serialize(self)?)
+    }
+    #[cfg(feature = "bincode")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<GameState, DynError> {
+        Ok(bincode::DefaultOptions::new().deserialize(bytes)?)
+    }
+    // Serializes the game as JSON, masking whatever the audience may not see.
+    // Every audience gets the pile counts. A player also gets the tokens they
+    // are missing for each market and reserved card, and the gold that would
+    // cover them, for affordability hints.
+    pub fn serialize_for(&self, audience: Audience) -> Result<String, DynError> {
+        let mut value = match audience {
+            Audience::Player(idx) => {
+                if idx >= self.players.len() {
+                    return Err("Invalid player index".into());
+                }
+                let player = &self.players[idx];
+                let mut value = serde_json::to_value(self.redacted_for(idx))?;
+                let hint = |card: &Card| {
+                    let tokens = player.missing_tokens(card);
+                    let gold: u8 = tokens.iter().sum();
+                    serde_json::json!({ "tokens": tokens, "gold": gold })
+                };
+                let market: Vec<Vec<_>> = self
+                    .market
+                    .iter()
+                    .map(|row| row.iter().map(hint).collect())
+                    .collect();
+                let reserved: Vec<_> = (0..player.num_reserved())
+                    .filter_map(|i| player.peek_reserved(i))
+                    .map(hint)
+                    .collect();
+                value["missing_tokens"] = serde_json::json!({
+                    "market": market,
+                    "reserved": reserved,
+                });
+                value
+            }
+            _ => serde_json::to_value(self)?,
+        };
+        if audience != Audience::Full {
+            let object = value.as_object_mut().ok_or("Invalid game state")?;
+            object.remove("piles");
+            object.remove("seed");
+        }
+        value["pile_counts"] = self.pile_counts().to_vec().into();
+        Ok(value.to_string())
+    }
     fn take_card(&mut self, loc: &CardLocation) -> Result<Card, DynError> {
         match loc {
             CardLocation::Pile(level) => {
@@ -208,24 +1152,127 @@ impl GameState {
                 .ok_or_else(|| "Invalid reserve index".into()),
         }
     }
-    pub fn valid_actions(&self) -> Vec<Action> {
-        let mut actions = Vec::new();
-        let player = self.curr_player();
-        // Try to buy every available card in the market.
-        for (level, market) in self.market.iter().enumerate() {
-            for (idx, card) in market.iter().enumerate() {
-                if player.can_buy(card) {
-                    actions.push(Action::BuyCard(CardLocation::Market(level + 1, idx)));
+    // Whether the bank holds the tokens a take would need. This is narrower
+    // than full legality: it ignores the player's token limit, and buys and
+    // reserves always pass since tokens returned to the bank came from it.
+    pub fn bank_can_support(&self, action: &Action) -> bool {
+        match action {
+            Action::TakeDifferentColorTokens(colors) => colors
+                .iter()
+                .all(|&c| c != Color::Gold && self.bank[c as usize] > 0),
+            Action::TakeSameColorTokens(color) => self.can_take_same(*color),
+            Action::ReserveCard(_)
+            | Action::BuyCard(_)
+            | Action::BuyCardWithPayment(..)
+            | Action::Pass
+            | Action::ReturnTokens(_)
+            | Action::AcquireNoble(_) => true,
+        }
+    }
+    // Whether the bank allows taking two tokens of this color: it must hold at
+    // least SAME_COLOR_BANK_MIN of them. (Going over the token limit is fine,
+    // since the excess is returned afterwards.) This is the single check
+    // shared by validate_action and the action listings.
+    pub fn can_take_same(&self, color: Color) -> bool {
+        color != Color::Gold && self.bank[color as usize] >= SAME_COLOR_BANK_MIN
+    }
+    // Colors the current player could take two of.
+    pub fn legal_same_color_takes(&self) -> Vec<Color> {
+        if self.pending.is_some() {
+            return Vec::new();
+        }
+        self.same_color_takes()
+    }
+    fn same_color_takes(&self) -> Vec<Color> {
+        (0..5)
+            .map(|i| i.try_into().unwrap())
+            .filter(|&c| self.can_take_same(c))
+            .collect()
+    }
+    pub fn available_actions_summary(&self) -> ActionSummary {
+        let mut summary = ActionSummary::default();
+        for action in self.valid_actions() {
+            match action {
+                Action::Pass => summary.can_pass = true,
+                Action::TakeDifferentColorTokens(_) | Action::TakeSameColorTokens(_) => {
+                    summary.token_takes += 1
                 }
+                Action::ReserveCard(_) => summary.reserves += 1,
+                Action::BuyCard(_) | Action::BuyCardWithPayment(..) => summary.buys += 1,
+                Action::ReturnTokens(_) => summary.returns += 1,
+                Action::AcquireNoble(_) => summary.nobles += 1,
             }
         }
-        // Try to buy every reserved card.
-        for idx in player.buyable_reserved_cards() {
-            actions.push(Action::BuyCard(CardLocation::Reserve(idx)));
-        }
-
-        // Reserve every available card (including piles) if we have fewer than
-        // 3 reserved already.
+        summary
+    }
+    pub fn valid_actions(&self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.valid_actions_into(&mut actions);
+        actions
+    }
+    // Like valid_actions, but fills the caller's buffer (clearing it first),
+    // so hot loops like rollouts can reuse one allocation.
+    pub fn valid_actions_into(&self, buf: &mut Vec<Action>) {
+        buf.clear();
+        if self.is_finished() {
+            return;
+        }
+        self.legal_actions_into(self.curr_player_idx, buf);
+        #[cfg(feature = "metrics")]
+        {
+            let mut metrics = self.metrics.get();
+            metrics.valid_actions_calls += 1;
+            metrics.max_actions = metrics.max_actions.max(buf.len());
+            self.metrics.set(metrics);
+        }
+    }
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.get()
+    }
+    // The actions the given player could take if it were their turn, using
+    // their tokens, cards, and reserves against the current board.
+    pub fn legal_actions_for(&self, player_idx: usize) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.legal_actions_into(player_idx, &mut actions);
+        actions
+    }
+    // Appends legal_actions_for's actions to an empty buffer.
+    fn legal_actions_into(&self, player_idx: usize, actions: &mut Vec<Action>) {
+        if player_idx == self.curr_player_idx {
+            match self.pending {
+                Some(PendingChoice::ReturnTokens(_)) => {
+                    actions.extend(self.legal_discards());
+                    return;
+                }
+                Some(PendingChoice::AcquireNoble) => {
+                    actions.extend(
+                        self.curr_player()
+                            .acquirable_nobles(&self.nobles)
+                            .into_iter()
+                            .map(Action::AcquireNoble),
+                    );
+                    return;
+                }
+                None => {}
+            }
+        }
+        let player = &self.players[player_idx];
+        // Try to buy every available card in the market.
+        for (level, market) in self.market.iter().enumerate() {
+            for (idx, card) in market.iter().enumerate() {
+                if player.can_buy(card) {
+                    actions.push(Action::BuyCard(CardLocation::Market(level + 1, idx)));
+                }
+            }
+        }
+        // Try to buy every reserved card.
+        for idx in player.buyable_reserved_cards() {
+            actions.push(Action::BuyCard(CardLocation::Reserve(idx)));
+        }
+
+        // Reserve every available card (including piles) if we have fewer than
+        // 3 reserved already.
         if player.can_reserve() {
             for (level, market) in self.market.iter().enumerate() {
                 for idx in 0..market.len() {
@@ -238,39 +1285,32 @@ impl GameState {
         }
 
         // Take tokens from the bank, if possible.
-        let num_tokens = player.num_tokens();
-        if num_tokens <= 8 {
-            for i in 0..5 {
-                if self.bank[i] >= 4 {
-                    actions.push(Action::TakeSameColorTokens(i.try_into().unwrap()));
-                }
-            }
+        for color in self.same_color_takes() {
+            actions.push(Action::TakeSameColorTokens(color));
         }
         // Take up to 3 different color tokens, if possible.
         let prev_num_actions = actions.len();
-        if num_tokens <= 7 {
-            for i in 0..3 {
-                if self.bank[i] == 0 {
+        for i in 0..3 {
+            if self.bank[i] == 0 {
+                continue;
+            }
+            for j in i + 1..4 {
+                if self.bank[j] == 0 {
                     continue;
                 }
-                for j in i + 1..4 {
-                    if self.bank[j] == 0 {
-                        continue;
-                    }
-                    for k in j + 1..5 {
-                        if self.bank[k] > 0 {
-                            actions.push(Action::TakeDifferentColorTokens(vec![
-                                i.try_into().unwrap(),
-                                j.try_into().unwrap(),
-                                k.try_into().unwrap(),
-                            ]));
-                        }
+                for k in j + 1..5 {
+                    if self.bank[k] > 0 {
+                        actions.push(Action::TakeDifferentColorTokens(vec![
+                            i.try_into().unwrap(),
+                            j.try_into().unwrap(),
+                            k.try_into().unwrap(),
+                        ]));
                     }
                 }
             }
         }
         // Only take two different color tokens if we can't take three.
-        if num_tokens <= 8 && actions.len() == prev_num_actions {
+        if actions.len() == prev_num_actions {
             for i in 0..4 {
                 if self.bank[i] == 0 {
                     continue;
@@ -286,7 +1326,7 @@ impl GameState {
             }
         }
         // Only take one single token if we can't take two.
-        if num_tokens <= 9 && actions.len() == prev_num_actions {
+        if actions.len() == prev_num_actions {
             for i in 0..5 {
                 if self.bank[i] > 0 {
                     actions.push(Action::TakeDifferentColorTokens(vec![i
@@ -298,14 +1338,322 @@ impl GameState {
 
         // As a last resort, do nothing.
         if actions.is_empty() {
-            actions.push(Action::TakeDifferentColorTokens(vec![]));
+            actions.push(Action::Pass);
         }
+    }
+    // The ways the current player can return tokens to get back down to the
+    // token limit, or nothing if they don't need to.
+    pub fn legal_discards(&self) -> Vec<Action> {
+        let Some(PendingChoice::ReturnTokens(n)) = self.pending else {
+            return Vec::new();
+        };
+        let mut discards = Vec::new();
+        let mut colors = Vec::new();
+        push_discards(&self.curr_player().tokens, 0, n, &mut colors, &mut discards);
+        discards
+    }
+    pub fn pending(&self) -> Option<PendingChoice> {
+        self.pending
+    }
+}
 
-        actions
+// Enumerates each multiset of `n` tokens drawn from `tokens`, using only
+// colors from index `start` onwards, in sorted order.
+fn push_discards(
+    tokens: &[u8; 6],
+    start: usize,
+    n: u8,
+    colors: &mut Vec<Color>,
+    out: &mut Vec<Action>,
+) {
+    if n == 0 {
+        out.push(Action::ReturnTokens(colors.clone()));
+        return;
+    }
+    for i in start..6 {
+        let used = colors.iter().filter(|&&c| c as usize == i).count() as u8;
+        if used < tokens[i] {
+            colors.push(i.try_into().unwrap());
+            push_discards(tokens, i, n - 1, colors, out);
+            colors.pop();
+        }
+    }
+}
+
+// Compact, single-line encoding of the public part of a game position, in the
+// spirit of chess FEN. Space-separated fields:
+//   round, current player, bank, players, market rows, nobles
+// where players are "tokens|discounts|vp" joined by '/', market rows are
+// joined by '/' with cards like "K1-01230" (color, VP, cost digits), and
+// nobles look like "3-44000". Hidden information (piles, reserves) is omitted.
+// Ids aren't recorded either; parsing recovers them from the built-in deck.
+impl GameState {
+    pub fn to_fen_like(&self) -> String {
+        let join = |xs: &[u8]| {
+            xs.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let digits = |xs: &[u8; 5]| xs.iter().map(|x| x.to_string()).collect::<String>();
+        let players = self
+            .players
+            .iter()
+            .map(|p| format!("{}|{}|{}", join(&p.tokens), join(&p.discounts()), p.vp()))
+            .collect::<Vec<_>>()
+            .join("/");
+        let market = self
+            .market
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| format!("{}{}-{}", c.color.symbol(), c.vp, digits(&c.cost)))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let nobles = self
+            .nobles
+            .iter()
+            .map(|n| format!("{}-{}", n.vp, digits(&n.cost)))
+            .collect::<Vec<_>>()
+            .join("/");
+        format!(
+            "{} {} {} {} {} {}",
+            self.round,
+            self.curr_player_idx,
+            join(&self.bank),
+            players,
+            market,
+            if nobles.is_empty() { "-" } else { &nobles }
+        )
+    }
+    pub fn from_fen_like(fen: &str) -> Result<GameState, DynError> {
+        let fields = fen.split(' ').collect::<Vec<_>>();
+        if fields.len() != 6 {
+            return Err("Expected 6 space-separated fields".into());
+        }
+        let round = fields[0].parse::<u16>()?;
+        let curr_player_idx = fields[1].parse::<usize>()?;
+        let bank = parse_counts::<6>(fields[2])?;
+        let mut players = Vec::new();
+        for p in fields[3].split('/') {
+            let parts = p.split('|').collect::<Vec<_>>();
+            if parts.len() != 3 {
+                return Err(format!("Invalid player: {}", p).into());
+            }
+            players.push(Player::from_public(
+                parse_counts::<6>(parts[0])?,
+                parse_counts::<5>(parts[1])?,
+                parts[2].parse()?,
+                round,
+            ));
+        }
+        if curr_player_idx >= players.len() {
+            return Err("Invalid current player".into());
+        }
+        let rows = fields[4].split('/').collect::<Vec<_>>();
+        if rows.len() != 3 {
+            return Err("Expected 3 market rows".into());
+        }
+        let deck = load_from_csv::<Card>(include_str!("../cards.csv"))?;
+        let noble_deck = load_from_csv::<Noble>(include_str!("../nobles.csv"))?;
+        let mut market = [Vec::new(), Vec::new(), Vec::new()];
+        for (level, row) in rows.iter().enumerate() {
+            for card in row.split(',').filter(|c| !c.is_empty()) {
+                let (head, cost) = card.split_once('-').ok_or("Invalid card")?;
+                let mut chars = head.chars();
+                let color = chars
+                    .next()
+                    .and_then(Color::from_symbol)
+                    .ok_or_else(|| format!("Invalid card color: {}", card))?;
+                let mut card = Card {
+                    id: 0,
+                    level: level + 1,
+                    color,
+                    vp: chars.as_str().parse()?,
+                    cost: parse_digits(cost)?,
+                };
+                card.id = deck
+                    .iter()
+                    .find(|c| {
+                        c.level == card.level
+                            && c.color == card.color
+                            && c.vp == card.vp
+                            && c.cost == card.cost
+                    })
+                    .map_or(0, |c| c.id);
+                market[level].push(card);
+            }
+        }
+        let mut nobles = Vec::new();
+        for noble in fields[5].split('/').filter(|n| *n != "-") {
+            let (vp, cost) = noble.split_once('-').ok_or("Invalid noble")?;
+            let mut noble = Noble {
+                id: 0,
+                vp: vp.parse()?,
+                cost: parse_digits(cost)?,
+            };
+            noble.id = noble_deck
+                .iter()
+                .find(|n| n.vp == noble.vp && n.cost == noble.cost)
+                .map_or(0, |n| n.id);
+            nobles.push(noble);
+        }
+        Ok(GameState {
+            piles: [Vec::new(), Vec::new(), Vec::new()],
+            market,
+            nobles,
+            bank,
+            players,
+            curr_player_idx,
+            // The FEN-like format doesn't record who started.
+            first_player: 0,
+            round,
+            // Nor how many turns were played.
+            turns: 0,
+            passes: 0,
+            finished: false,
+            outcome: None,
+            ruleset: Ruleset::standard(),
+            seed: None,
+            pending: None,
+            history: Vec::new(),
+            #[cfg(feature = "metrics")]
+            metrics: Cell::default(),
+        })
+    }
+}
+
+// Fixed-length numeric encoding of a game from one player's perspective, for
+// machine learning. The layout, in order:
+//   bank (6)
+//   MAX_PLAYERS players, starting with "me" and continuing in turn order, each
+//     tokens (6), discounts (5), VP, number of reserved cards; missing seats
+//     are all zeros
+//   market: 3 levels x 4 slots of cards
+//   MAX_PLAYERS + 1 nobles, each VP then cost (5)
+//   my 3 reserved cards
+// where each card is a one-hot color (5), VP, then cost (5), and empty card
+// or noble slots are all zeros.
+const ENCODED_PLAYER_LEN: usize = 13;
+const ENCODED_CARD_LEN: usize = 11;
+const ENCODED_NOBLE_LEN: usize = 6;
+impl GameState {
+    pub const fn encode_len() -> usize {
+        6 + MAX_PLAYERS * ENCODED_PLAYER_LEN
+            + 12 * ENCODED_CARD_LEN
+            + (MAX_PLAYERS + 1) * ENCODED_NOBLE_LEN
+            + 3 * ENCODED_CARD_LEN
+    }
+    pub fn encode(&self, player_idx: usize) -> Vec<f32> {
+        let mut out = Vec::with_capacity(Self::encode_len());
+        out.extend(self.bank.iter().map(|&n| n as f32));
+        let n = self.players.len();
+        for seat in 0..MAX_PLAYERS {
+            if seat >= n {
+                out.extend([0.0; ENCODED_PLAYER_LEN]);
+                continue;
+            }
+            let p = &self.players[(player_idx + seat) % n];
+            out.extend(p.tokens.iter().map(|&t| t as f32));
+            out.extend(p.discounts().iter().map(|&d| d as f32));
+            out.push(p.vp() as f32);
+            out.push((0..).map_while(|i| p.peek_reserved(i)).count() as f32);
+        }
+        for row in self.market.iter() {
+            for slot in 0..4 {
+                encode_card(&mut out, row.get(slot));
+            }
+        }
+        for slot in 0..=MAX_PLAYERS {
+            match self.nobles.get(slot) {
+                Some(noble) => {
+                    out.push(noble.vp as f32);
+                    out.extend(noble.cost.iter().map(|&c| c as f32));
+                }
+                None => out.extend([0.0; ENCODED_NOBLE_LEN]),
+            }
+        }
+        let me = &self.players[player_idx];
+        for slot in 0..3 {
+            encode_card(&mut out, me.peek_reserved(slot));
+        }
+        out
+    }
+}
+
+fn encode_card(out: &mut Vec<f32>, card: Option<&Card>) {
+    let Some(card) = card else {
+        out.extend([0.0; ENCODED_CARD_LEN]);
+        return;
+    };
+    let mut color = [0.0; 5];
+    color[card.color as usize] = 1.0;
+    out.extend(color);
+    out.push(card.vp as f32);
+    out.extend(card.cost.iter().map(|&c| c as f32));
+}
+
+fn parse_counts<const N: usize>(s: &str) -> Result<[u8; N], DynError> {
+    let counts = s
+        .split(',')
+        .map(|x| x.parse())
+        .collect::<Result<Vec<u8>, _>>()?;
+    counts
+        .try_into()
+        .map_err(|_| format!("Expected {} counts: {}", N, s).into())
+}
+
+fn parse_digits(s: &str) -> Result<[u8; 5], DynError> {
+    let digits = s
+        .chars()
+        .map(|c| c.to_digit(10).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("Invalid cost: {}", s))?;
+    digits
+        .try_into()
+        .map_err(|_| format!("Expected 5 cost digits: {}", s).into())
+}
+
+// Checks that every noble has a sensible VP value and a cost that can
+// actually be met with the cards in the deck.
+fn validate_nobles(nobles: &[Noble], cards: &[Card]) -> Result<(), DynError> {
+    let mut cards_per_color = [0usize; 5];
+    for card in cards.iter() {
+        if let Some(n) = cards_per_color.get_mut(card.color as usize) {
+            *n += 1;
+        }
     }
+    for (i, noble) in nobles.iter().enumerate() {
+        // Row numbers are 1-based and skip the CSV header.
+        let row = i + 2;
+        if !(1..=5).contains(&noble.vp) {
+            return Err(format!("Noble on row {} has implausible VP: {}", row, noble.vp).into());
+        }
+        if noble.cost.iter().all(|&c| c == 0) {
+            return Err(format!("Noble on row {} has no cost", row).into());
+        }
+        for (c, (&cost, &available)) in noble.cost.iter().zip(cards_per_color.iter()).enumerate() {
+            if cost as usize > available {
+                return Err(format!(
+                    "Noble on row {} needs {} {:?} cards, but the deck only has {}",
+                    row,
+                    cost,
+                    Color::try_from(c).unwrap(),
+                    available
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
 }
 
-fn load_from_csv<T: for<'de> Deserialize<'de>>(data: &str) -> Result<Vec<T>, DynError> {
+// Parses cards or nobles laid out like cards.csv and nobles.csv, e.g. to
+// build a custom deck for init_with_deck.
+pub fn load_from_csv<T: for<'de> Deserialize<'de>>(data: &str) -> Result<Vec<T>, DynError> {
     let mut rdr = csv::ReaderBuilder::new()
         .flexible(true)
         .from_reader(data.as_bytes());
@@ -320,6 +1668,9 @@ fn load_from_csv<T: for<'de> Deserialize<'de>>(data: &str) -> Result<Vec<T>, Dyn
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::agent::{create_agent, NoisyAgent, RandomAgent};
+    use crate::data_types::PendingChoice;
+    use crate::testing::GameStateBuilder;
 
     #[test]
     fn load_cards_from_csv() {
@@ -330,6 +1681,53 @@ mod tests {
         )
         .unwrap();
         assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].id, 0);
+    }
+
+    #[test]
+    fn card_ids() {
+        let cards = load_from_csv::<Card>(include_str!("../cards.csv")).unwrap();
+        assert!(cards
+            .iter()
+            .enumerate()
+            .all(|(i, c)| c.id as usize == i + 1));
+        let nobles = load_from_csv::<Noble>(include_str!("../nobles.csv")).unwrap();
+        assert!(nobles
+            .iter()
+            .enumerate()
+            .all(|(i, n)| n.id as usize == i + 1));
+
+        // Decks without ids are numbered in order.
+        let mut cards: Vec<Card> = cards.into_iter().map(|c| Card { id: 0, ..c }).collect();
+        let gs = GameState::init_with_deck(2, 0, cards.clone(), vec![]).unwrap();
+        let mut ids: Vec<u16> = gs
+            .market
+            .iter()
+            .chain(&gs.piles)
+            .flatten()
+            .map(|c| c.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (1..=cards.len() as u16).collect::<Vec<_>>());
+
+        // Ids stay attached to their cards when they're reserved or bought,
+        // including the card that refills the market.
+        cards[0].id = 500;
+        let mut gs = GameState::init_with_deck(2, 0, cards, vec![]).unwrap();
+        let refill = gs.piles[0].last().unwrap().clone();
+        let target = gs.market[0][0].clone();
+        let outcome = gs
+            .take_turn(&Action::ReserveCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        assert_eq!(outcome.card, Some(target.clone()));
+        assert_eq!(gs.players[gs.first_player].peek_reserved(0), Some(&target));
+        assert_eq!(gs.market[0].iter().filter(|c| **c == refill).count(), 1);
+
+        let top = gs.piles[1].last().unwrap().clone();
+        let outcome = gs
+            .take_turn(&Action::ReserveCard(CardLocation::Pile(2)))
+            .unwrap();
+        assert_eq!(outcome.card.map(|c| c.id), Some(top.id));
     }
 
     #[test]
@@ -344,6 +1742,149 @@ mod tests {
         assert_eq!(nobles.len(), 3);
     }
 
+    // Three players with nothing in the bank, nothing left to reserve, and
+    // nothing affordable, so all they can do is pass.
+    fn stalemated_game() -> GameState {
+        let card = |vp| Card {
+            id: 0,
+            level: 1,
+            color: Color::Red,
+            vp,
+            cost: [7, 7, 7, 7, 7],
+        };
+        let mut gs = GameStateBuilder::new(3)
+            .bank([0; 6])
+            .market(1, vec![])
+            .market(2, vec![])
+            .market(3, vec![])
+            .reserved(0, vec![card(0), card(0), card(0)])
+            .reserved(1, vec![card(0), card(0), card(0)])
+            .reserved(2, vec![card(0), card(0), card(0)])
+            .owned(2, vec![card(2)])
+            .curr_player(1)
+            .build()
+            .unwrap();
+        for level in 1..=3 {
+            gs.stack_pile(level, vec![]).unwrap();
+        }
+        gs
+    }
+
+    #[test]
+    fn stalemate() {
+        let mut gs = stalemated_game();
+        // The middle pass uses the legacy encoding, an empty take.
+        let passes = [
+            Action::Pass,
+            Action::TakeDifferentColorTokens(vec![]),
+            Action::Pass,
+        ];
+        for (i, (pass, expect_over)) in passes.iter().zip([false, false, true]).enumerate() {
+            assert_eq!(gs.valid_actions(), vec![Action::Pass]);
+            assert_eq!(gs.turn_count(), i as u32);
+            assert_eq!(gs.take_turn(pass).unwrap().is_over, expect_over);
+        }
+        assert!(gs.history.iter().all(|(_, a)| *a == Action::Pass));
+        assert!(gs.is_finished());
+        assert_eq!(gs.outcome(), Some(GameOutcome::Stalemate));
+        assert_eq!(gs.winners(), vec![2]);
+    }
+
+    // Takes tokens and hands them straight back, so the game never ends.
+    struct ChurnAgent;
+    impl Agent for ChurnAgent {
+        fn choose_action(&self, game: &GameState) -> Action {
+            let actions = game.valid_actions();
+            let churn = |a: &&Action| {
+                matches!(
+                    a,
+                    Action::TakeDifferentColorTokens(_) | Action::ReturnTokens(_)
+                )
+            };
+            actions.iter().find(churn).unwrap_or(&actions[0]).clone()
+        }
+    }
+
+    #[test]
+    fn simulate_times_out() {
+        let mut gs = GameStateBuilder::new(2)
+            .tokens(0, [2, 2, 2, 2, 2, 0])
+            .tokens(1, [2, 2, 2, 2, 2, 0])
+            .bank([4, 4, 4, 4, 4, 5])
+            .build()
+            .unwrap();
+        assert!(gs.simulate_to_end_with(&ChurnAgent).is_err());
+        assert_eq!(gs.outcome(), Some(GameOutcome::Timeout));
+        assert!(gs.is_finished());
+        assert_eq!(gs.winners(), vec![0, 1]);
+        assert!(gs.valid_actions().is_empty());
+        assert!(gs.take_turn(&Action::Pass).is_err());
+        assert_eq!(gs.outcome(), Some(GameOutcome::Timeout));
+    }
+
+    #[test]
+    fn voluntary_passes_are_no_stalemate() {
+        let mut gs = GameState::init(2).unwrap();
+        for _ in 0..4 {
+            assert!(!gs.take_turn(&Action::Pass).unwrap().is_over);
+        }
+        assert!(!gs.is_finished());
+        assert_eq!(gs.outcome(), None);
+    }
+
+    #[test]
+    fn simulate_finishes_at_the_cap() {
+        // The stalemate takes exactly 3 passes.
+        let agent = create_agent(1);
+        let mut gs = stalemated_game();
+        assert_eq!(
+            gs.simulate_within(agent.as_ref(), 3).unwrap(),
+            vec![0, 0, 2]
+        );
+        assert_eq!(gs.outcome(), Some(GameOutcome::Stalemate));
+
+        let mut gs = stalemated_game();
+        assert!(gs.simulate_within(agent.as_ref(), 2).is_err());
+        assert_eq!(gs.outcome(), Some(GameOutcome::Timeout));
+    }
+
+    #[test]
+    fn scoreboard() {
+        let mut gs = GameState::init(2).unwrap();
+        give_card(&mut gs.players[1], Color::White);
+        give_card(&mut gs.players[1], Color::White);
+        gs.players[1].tokens = [0, 0, 0, 0, 0, 3];
+        let card = gs.market[0][0].clone();
+        gs.players[1].reserve(card, &mut 0, Ruleset::MAX_TOKENS);
+        assert_eq!(
+            gs.scoreboard(),
+            vec![
+                PlayerSummary::default(),
+                PlayerSummary {
+                    vp: 0,
+                    tokens: [0, 0, 0, 0, 0, 3],
+                    cards: [2, 0, 0, 0, 0],
+                    nobles: 0,
+                    reserved: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn pile_counts() {
+        let mut gs = GameState::init(2).unwrap();
+        assert_eq!(gs.pile_counts(), [36, 26, 16]);
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(2, 1)))
+            .unwrap();
+        assert_eq!(gs.pile_counts(), [36, 25, 16]);
+        gs.take_turn(&Action::ReserveCard(CardLocation::Pile(3)))
+            .unwrap();
+        assert_eq!(gs.pile_counts(), [36, 25, 15]);
+        // The counts are public, but the top card still isn't.
+        assert!(gs.peek_card(&CardLocation::Pile(1)).is_err());
+    }
+
     #[test]
     fn init() {
         let gs = GameState::init(2).unwrap();
@@ -360,19 +1901,23 @@ mod tests {
     fn game_turns() {
         let mut gs = GameState::init(2).unwrap();
         let starting_idx = gs.curr_player_idx;
-        assert!(!gs
-            .take_turn(&Action::TakeDifferentColorTokens(vec![
+        assert!(
+            !gs.take_turn(&Action::TakeDifferentColorTokens(vec![
                 Color::White,
                 Color::Blue,
                 Color::Green
             ]))
-            .unwrap());
+            .unwrap()
+            .is_over
+        );
         assert_eq!(gs.players[starting_idx].num_tokens(), 3);
         let other_idx = gs.curr_player_idx;
         assert_ne!(other_idx, starting_idx);
-        assert!(!gs
-            .take_turn(&Action::TakeSameColorTokens(Color::Red))
-            .unwrap());
+        assert!(
+            !gs.take_turn(&Action::TakeSameColorTokens(Color::Red))
+                .unwrap()
+                .is_over
+        );
         assert_eq!(gs.players[other_idx].num_tokens(), 2);
         assert_eq!(gs.curr_player_idx, starting_idx);
     }
@@ -415,6 +1960,25 @@ mod tests {
                 Action::TakeDifferentColorTokens(vec![Color::Green, Color::Red, Color::Black])
             ]
         );
+        // All generated actions are already in canonical form.
+        for action in gs.valid_actions() {
+            assert_eq!(action.clone().normalized(), action);
+        }
+    }
+
+    #[test]
+    fn valid_actions_into_matches() {
+        let agent = NoisyAgent::seeded(RandomAgent, 0.0, 19);
+        let mut gs = GameState::init_seeded(3, 19).unwrap();
+        let mut buf = vec![Action::Pass];
+        while !gs.is_finished() && gs.turn_count() < 300 {
+            gs.valid_actions_into(&mut buf);
+            assert_eq!(buf, gs.valid_actions());
+            gs.take_turn(&agent.choose_action(&gs)).unwrap();
+        }
+        gs.finished = true;
+        gs.valid_actions_into(&mut buf);
+        assert!(buf.is_empty());
     }
 
     #[test]
@@ -426,66 +1990,1688 @@ mod tests {
         gs.market[2].clear();
 
         {
-            let mut player = &mut gs.players[gs.curr_player_idx];
+            let player = &mut gs.players[gs.curr_player_idx];
             // Fill the player's token quota, so they can't take any more.
             player.tokens[0] = 10;
             // Fill the player's reserve, so they can't reserve any more.
             player.reserve(
                 Card {
+                    id: 0,
                     level: 1,
                     color: Color::White,
                     vp: 0,
                     cost: [1, 1, 1, 1, 0],
                 },
                 &mut gs.bank[5],
+                Ruleset::MAX_TOKENS,
             );
             player.reserve(
                 Card {
+                    id: 0,
                     level: 1,
                     color: Color::Green,
                     vp: 0,
                     cost: [1, 1, 1, 1, 0],
                 },
                 &mut gs.bank[5],
+                Ruleset::MAX_TOKENS,
             );
             player.reserve(
                 Card {
+                    id: 0,
                     level: 1,
                     color: Color::Blue,
                     vp: 0,
                     cost: [1, 1, 1, 1, 0],
                 },
                 &mut gs.bank[5],
+                Ruleset::MAX_TOKENS,
             );
         }
+        // Even at the token limit, tokens can be taken (and then returned).
+        assert!(gs.valid_actions().iter().all(
+            |a| matches!(a, Action::TakeDifferentColorTokens(c) if c.len() == 3)
+                || matches!(a, Action::TakeSameColorTokens(_))
+        ));
+
+        // With only a few tokens in the bank, take as many colors as possible.
+        gs.bank = [0, 0, 1, 1, 1, 0];
         assert_eq!(
             gs.valid_actions(),
-            vec![Action::TakeDifferentColorTokens(vec![])]
+            vec![Action::TakeDifferentColorTokens(vec![
+                Color::Green,
+                Color::Red,
+                Color::Black
+            ])]
         );
-
-        // If we have 9 tokens, we can take a single token of any available color.
-        gs.players[gs.curr_player_idx].tokens[0] = 9;
+        gs.bank = [0, 0, 0, 1, 1, 0];
         assert_eq!(
             gs.valid_actions(),
-            vec![
-                Action::TakeDifferentColorTokens(vec![Color::White]),
-                Action::TakeDifferentColorTokens(vec![Color::Blue]),
-                Action::TakeDifferentColorTokens(vec![Color::Green]),
-                Action::TakeDifferentColorTokens(vec![Color::Red]),
-                Action::TakeDifferentColorTokens(vec![Color::Black])
-            ]
+            vec![Action::TakeDifferentColorTokens(vec![
+                Color::Red,
+                Color::Black
+            ])]
         );
-
-        // Ensure we omit colors that have no tokens available in the bank.
-        gs.bank[0] = 0;
-        gs.bank[1] = 0;
+        gs.bank = [0, 0, 0, 0, 1, 0];
         assert_eq!(
             gs.valid_actions(),
-            vec![
-                Action::TakeDifferentColorTokens(vec![Color::Green]),
-                Action::TakeDifferentColorTokens(vec![Color::Red]),
-                Action::TakeDifferentColorTokens(vec![Color::Black])
-            ]
+            vec![Action::TakeDifferentColorTokens(vec![Color::Black])]
+        );
+
+        // With an empty bank, there's nothing to do.
+        gs.bank = [0, 0, 0, 0, 0, 0];
+        assert_eq!(gs.valid_actions(), vec![Action::Pass]);
+    }
+
+    #[test]
+    fn return_tokens_over_limit() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.players[idx].tokens = [3, 3, 3, 0, 0, 0];
+        gs.take_turn(&Action::take_different(&[
+            Color::Green,
+            Color::Red,
+            Color::Black,
+        ]))
+        .unwrap();
+        assert_eq!(gs.curr_player_idx, idx);
+        assert_eq!(gs.pending(), Some(PendingChoice::ReturnTokens(2)));
+        assert_eq!(gs.players[idx].num_tokens(), 12);
+
+        // Only returns are allowed: any 2 of the 5 held colors, or a double
+        // of the 3 colors held at least twice.
+        let discards = gs.legal_discards();
+        assert_eq!(gs.valid_actions(), discards);
+        assert_eq!(discards.len(), 10 + 3);
+        assert!(discards.contains(&Action::ReturnTokens(vec![Color::White, Color::White])));
+        assert!(!discards.contains(&Action::ReturnTokens(vec![Color::Red, Color::Red])));
+        assert_eq!(
+            gs.take_turn(&Action::TakeSameColorTokens(Color::Blue))
+                .unwrap_err()
+                .to_string(),
+            "Must return tokens first"
+        );
+        assert_eq!(
+            gs.take_turn(&Action::ReturnTokens(vec![Color::White]))
+                .unwrap_err()
+                .to_string(),
+            "Must return exactly 2 tokens"
+        );
+        assert_eq!(
+            gs.take_turn(&Action::ReturnTokens(vec![Color::Red, Color::Red]))
+                .unwrap_err()
+                .to_string(),
+            "Cannot return tokens you don't have"
+        );
+
+        let bank_before = gs.bank;
+        gs.take_turn(&Action::ReturnTokens(vec![Color::White, Color::Red]))
+            .unwrap();
+        assert_eq!(gs.players[idx].tokens, [2, 3, 4, 0, 1, 0]);
+        assert_eq!(gs.bank[0], bank_before[0] + 1);
+        assert_eq!(gs.bank[3], bank_before[3] + 1);
+        assert_eq!(gs.pending(), None);
+        assert_ne!(gs.curr_player_idx, idx);
+        assert_eq!(
+            gs.take_turn(&Action::ReturnTokens(vec![Color::White]))
+                .unwrap_err()
+                .to_string(),
+            "No tokens to return"
+        );
+    }
+
+    #[test]
+    fn malformed_card_locations() {
+        let mut gs = GameState::init(2).unwrap();
+        let starting_idx = gs.curr_player_idx;
+        let cases = [
+            (CardLocation::Pile(0), "Invalid pile level"),
+            (CardLocation::Pile(4), "Invalid pile level"),
+            (CardLocation::Market(0, 0), "Invalid market level"),
+            (CardLocation::Market(4, 0), "Invalid market level"),
+            (CardLocation::Market(1, 4), "Invalid market index"),
+            (CardLocation::Reserve(99), "Invalid reserve index"),
+        ];
+        for (loc, msg) in cases.iter() {
+            if !matches!(loc, CardLocation::Reserve(_)) {
+                let err = gs.take_turn(&Action::ReserveCard(loc.clone()));
+                assert_eq!(err.unwrap_err().to_string(), *msg);
+            }
+            if !matches!(loc, CardLocation::Pile(_)) {
+                let err = gs.take_turn(&Action::BuyCard(loc.clone()));
+                assert_eq!(err.unwrap_err().to_string(), *msg);
+            }
+        }
+        assert_eq!(
+            gs.take_turn(&Action::BuyCard(CardLocation::Pile(1)))
+                .unwrap_err()
+                .to_string(),
+            "Cannot buy a card from the pile"
+        );
+        assert_eq!(
+            gs.take_turn(&Action::ReserveCard(CardLocation::Reserve(0)))
+                .unwrap_err()
+                .to_string(),
+            "Card is already reserved"
+        );
+        // None of the rejected actions should have changed the game state.
+        assert_eq!(gs.curr_player_idx, starting_idx);
+        assert_eq!(gs.market.iter().map(|m| m.len()).sum::<usize>(), 12);
+        assert_eq!(gs.piles.iter().map(|p| p.len()).sum::<usize>(), 78);
+    }
+
+    #[test]
+    fn fast_forward_stops_for_humans() {
+        let mut gs = GameState::init(3).unwrap();
+        gs.curr_player_idx = 0;
+        let agents = vec![Some(create_agent(1)), Some(create_agent(1)), None];
+        let mut seen = Vec::new();
+        let played = gs
+            .fast_forward(&agents, &mut |i, a, _| seen.push((i, a.clone())))
+            .unwrap();
+        assert_eq!(played.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(seen, played);
+        assert_eq!(gs.curr_player_idx, 2);
+
+        // Nothing happens if a human is already up.
+        assert!(gs
+            .fast_forward(&agents, &mut |_, _, _| {})
+            .unwrap()
+            .is_empty());
+        assert_eq!(gs.curr_player_idx, 2);
+    }
+
+    #[test]
+    fn fast_forward_times_out() {
+        let mut gs = GameStateBuilder::new(2)
+            .tokens(0, [2, 2, 2, 2, 2, 0])
+            .tokens(1, [2, 2, 2, 2, 2, 0])
+            .bank([4, 4, 4, 4, 4, 5])
+            .build()
+            .unwrap();
+        let agents: Vec<Option<Box<dyn Agent + Send>>> =
+            vec![Some(Box::new(ChurnAgent)), Some(Box::new(ChurnAgent))];
+        let mut turns = 0;
+        assert!(gs.fast_forward(&agents, &mut |_, _, _| turns += 1).is_err());
+        assert_eq!(turns, MAX_SIMULATED_TURNS);
+        assert_eq!(gs.outcome(), Some(GameOutcome::Timeout));
+    }
+
+    #[test]
+    fn simulate_to_end() {
+        let mut gs = GameState::init(2).unwrap();
+        let agent = create_agent(1);
+        let scores = gs.simulate_to_end_with(agent.as_ref()).unwrap();
+        assert!(gs.is_finished());
+        let winner = gs.winner().unwrap();
+        assert_eq!(scores[winner], *scores.iter().max().unwrap());
+        // No more actions can be taken once the game is over.
+        assert!(gs.valid_actions().is_empty());
+        let action = Action::TakeDifferentColorTokens(vec![Color::White]);
+        assert_eq!(
+            gs.take_turn(&action).unwrap_err().to_string(),
+            "Game is over"
+        );
+        assert_eq!(scores.len(), 2);
+        assert!(scores.iter().any(|&vp| vp >= 15));
+    }
+
+    fn give_card(player: &mut Player, color: Color) {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color,
+            vp: 0,
+            cost: [0, 0, 0, 0, 0],
+        };
+        player.buy(card, &mut [0; 6]);
+    }
+
+    #[test]
+    fn noble_competition_pressure() {
+        let mut gs = GameState::init(3).unwrap();
+        gs.nobles = vec![
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [4, 4, 0, 0, 0],
+            },
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 3, 3, 3],
+            },
+        ];
+        assert_eq!(gs.noble_competition_pressure(), vec![0, 0]);
+        // Players 0 and 1 are both one white card away from the first noble.
+        for p in gs.players[0..2].iter_mut() {
+            for _ in 0..3 {
+                give_card(p, Color::White);
+            }
+            for _ in 0..4 {
+                give_card(p, Color::Blue);
+            }
+        }
+        // Player 2 is further away from the second noble.
+        give_card(&mut gs.players[2], Color::Red);
+        assert_eq!(gs.noble_competition_pressure(), vec![2, 0]);
+    }
+
+    #[test]
+    fn market_refill_preview() {
+        let mut gs = GameState::init_seeded(2, 1234).unwrap();
+        let loc = CardLocation::Market(1, 2);
+        assert!(gs.market_refill_preview(&CardLocation::Pile(1)).is_none());
+        assert!(gs
+            .market_refill_preview(&CardLocation::Market(1, 4))
+            .is_none());
+        let preview = gs.market_refill_preview(&loc).unwrap().clone();
+        gs.players[gs.curr_player_idx].tokens = [7, 7, 7, 7, 7, 0];
+        gs.take_turn(&Action::BuyCard(loc)).unwrap();
+        assert_eq!(gs.market[0][2], preview);
+
+        // Once the pile is empty, nothing will refill the slot.
+        gs.piles[0].clear();
+        assert!(gs
+            .market_refill_preview(&CardLocation::Market(1, 0))
+            .is_none());
+    }
+
+    #[test]
+    fn noble_on_final_buy_wins() {
+        let mut gs = GameState::init(2).unwrap();
+        gs.curr_player_idx = 1;
+        gs.first_player = 0;
+        gs.nobles = vec![Noble {
+            id: 0,
+            vp: 3,
+            cost: [4, 4, 0, 0, 0],
+        }];
+        let p = &mut gs.players[1];
+        p.vp_history.push((1, 12));
+        for _ in 0..4 {
+            give_card(p, Color::White);
+        }
+        for _ in 0..3 {
+            give_card(p, Color::Blue);
+        }
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Blue,
+            vp: 0,
+            cost: [0, 0, 0, 0, 0],
+        };
+        // The card itself is worth nothing, but the noble pushes them to 15.
+        assert!(
+            gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+                .unwrap()
+                .is_over
+        );
+        assert_eq!(gs.players[1].vp(), 15);
+        assert_eq!(gs.players[1].nobles.len(), 1);
+        assert!(gs.nobles.is_empty());
+        assert_eq!(gs.winner(), Some(1));
+        assert_eq!(gs.outcome(), Some(GameOutcome::VictoryThreshold));
+    }
+
+    #[test]
+    fn fen_like_round_trip() {
+        let mut gs = GameState::init_seeded(3, 99).unwrap();
+        let agent = create_agent(2);
+        for _ in 0..20 {
+            let action = agent.choose_action(&gs);
+            gs.take_turn(&action).unwrap();
+        }
+        let fen = gs.to_fen_like();
+        let restored = GameState::from_fen_like(&fen).unwrap();
+        assert_eq!(restored.to_fen_like(), fen);
+        assert_eq!(restored.round, gs.round);
+        assert_eq!(restored.curr_player_idx, gs.curr_player_idx);
+        assert_eq!(restored.bank, gs.bank);
+        assert_eq!(restored.market, gs.market);
+        assert_eq!(restored.nobles, gs.nobles);
+        for (a, b) in restored.players.iter().zip(gs.players.iter()) {
+            assert_eq!(a.tokens, b.tokens);
+            assert_eq!(a.purchasing_power(false), b.purchasing_power(false));
+            assert_eq!(a.vp(), b.vp());
+        }
+        assert!(GameState::from_fen_like("1 0 4,4,4,4,4,5").is_err());
+    }
+
+    #[test]
+    fn round_and_turn_count() {
+        let mut gs = GameState::init(3).unwrap();
+        let take = Action::take_different(&[Color::White, Color::Blue, Color::Red]);
+        for turn in 0..3 {
+            assert_eq!(gs.round(), 1);
+            assert_eq!(gs.turn_count(), turn);
+            gs.take_turn(&take).unwrap();
+        }
+        assert_eq!(gs.round(), 2);
+        assert_eq!(gs.turn_count(), 3);
+
+        // Returning excess tokens finishes the turn rather than starting one.
+        let idx = gs.curr_player_idx;
+        gs.players[idx].tokens = [2, 2, 2, 2, 1, 0];
+        gs.take_turn(&take).unwrap();
+        assert_eq!(gs.turn_count(), 3);
+        gs.take_turn(&Action::ReturnTokens(vec![Color::Black, Color::Red]))
+            .unwrap();
+        assert_eq!(gs.turn_count(), 4);
+        assert_ne!(gs.curr_player_idx, idx);
+    }
+
+    #[test]
+    fn last_round_signal() {
+        let mut gs = GameState::init(3).unwrap();
+        gs.curr_player_idx = 0;
+        gs.first_player = 0;
+        gs.players[0].vp_history.push((1, 14));
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Blue,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        assert!(!gs.is_last_round());
+        let take = Action::TakeDifferentColorTokens(vec![Color::White]);
+        assert!(
+            !gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+                .unwrap()
+                .is_over
+        );
+        assert!(gs.is_last_round());
+        assert!(!gs.take_turn(&take).unwrap().is_over);
+        assert!(gs.is_last_round());
+        assert_eq!(gs.outcome(), None);
+        assert!(gs.take_turn(&take).unwrap().is_over);
+        assert_eq!(gs.outcome(), Some(GameOutcome::VictoryThreshold));
+        assert!(!gs.is_last_round());
+    }
+
+    #[test]
+    fn actions_summary() {
+        let mut gs = GameState::init(2).unwrap();
+        assert_eq!(
+            gs.available_actions_summary(),
+            ActionSummary {
+                buys: 0,
+                reserves: 15,
+                token_takes: 15,
+                can_pass: false,
+                returns: 0,
+                nobles: 0,
+            }
+        );
+        gs.players[gs.curr_player_idx].tokens = [4, 4, 4, 4, 4, 0];
+        let summary = gs.available_actions_summary();
+        let buys = gs
+            .valid_actions()
+            .iter()
+            .filter(|a| matches!(a, Action::BuyCard(_)))
+            .count();
+        assert_eq!(summary.buys, buys);
+        assert!(summary.buys > 0);
+        // Takes past the token limit are still allowed, followed by a return.
+        assert_eq!(summary.token_takes, 5 + 10);
+    }
+
+    #[test]
+    fn token_pressure() {
+        let mut gs = GameState::init(2).unwrap();
+        assert_eq!(gs.token_supply_total(), [4, 4, 4, 4, 4, 5]);
+        assert_eq!(gs.token_pressure(), [0.0; 5]);
+        gs.players[0].add_tokens(Color::Green, 3, &mut gs.bank);
+        gs.players[1].add_tokens(Color::Red, 1, &mut gs.bank);
+        gs.players[1].add_tokens(Color::Gold, 1, &mut gs.bank);
+        assert_eq!(gs.token_supply_total(), [4, 4, 4, 4, 4, 5]);
+        assert_eq!(gs.token_pressure(), [0.0, 0.0, 0.75, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn ruleset_round_trip() {
+        let gs = GameState::init(2).unwrap().with_ruleset(Ruleset::quick());
+        assert_eq!(gs.target_vp(), 10);
+        let json = serde_json::to_string(&gs).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.ruleset(), &Ruleset::quick());
+        assert_eq!(restored.target_vp(), 10);
+
+        // Games serialized before rulesets existed get the standard rules.
+        let mut legacy: serde_json::Value = serde_json::from_str(&json).unwrap();
+        legacy.as_object_mut().unwrap().remove("ruleset");
+        let restored: GameState = serde_json::from_value(legacy).unwrap();
+        assert_eq!(restored.target_vp(), 15);
+    }
+
+    #[test]
+    fn market_signature() {
+        let a = GameState::init_seeded(2, 7).unwrap();
+        let mut b = GameState::init_seeded(2, 7).unwrap();
+        let c = GameState::init_seeded(2, 8).unwrap();
+        assert_eq!(a.hash_market_signature(), b.hash_market_signature());
+        assert_ne!(a.hash_market_signature(), c.hash_market_signature());
+        // Player state doesn't affect the signature, but the bank does.
+        b.players[0].tokens[5] = 1;
+        assert_eq!(a.hash_market_signature(), b.hash_market_signature());
+        b.bank[5] -= 1;
+        assert_ne!(a.hash_market_signature(), b.hash_market_signature());
+
+        // The value itself is pinned, since saved opening books depend on it.
+        let card = |id, level| Card {
+            id,
+            level,
+            color: Color::White,
+            vp: 0,
+            cost: [1; 5],
+        };
+        let noble = |id| Noble {
+            id,
+            vp: 3,
+            cost: [3; 5],
+        };
+        let gs = GameStateBuilder::new(2)
+            .market(1, vec![card(1, 1), card(2, 1), card(3, 1), card(4, 1)])
+            .market(2, vec![card(41, 2), card(42, 2), card(43, 2), card(44, 2)])
+            .market(3, vec![card(71, 3), card(72, 3), card(73, 3), card(74, 3)])
+            .nobles(vec![noble(1), noble(2), noble(3)])
+            .bank([4, 4, 4, 4, 4, 5])
+            .build()
+            .unwrap();
+        assert_eq!(gs.hash_market_signature(), 0x9b65_50b9_0f11_aab7);
+    }
+
+    #[test]
+    fn validate_noble_costs() {
+        let cards = load_from_csv::<Card>(include_str!("../cards.csv")).unwrap();
+        let nobles = load_from_csv::<Noble>(include_str!("../nobles.csv")).unwrap();
+        assert!(validate_nobles(&nobles, &cards).is_ok());
+
+        let nobles = load_from_csv::<Noble>(
+            "vp,cost\n\
+        3,0,0,4,4,0\n\
+        3,50,0,0,0,0",
+        )
+        .unwrap();
+        assert_eq!(
+            validate_nobles(&nobles, &cards).unwrap_err().to_string(),
+            "Noble on row 3 needs 50 White cards, but the deck only has 18"
+        );
+        let nobles = load_from_csv::<Noble>("vp,cost\n200,0,0,4,4,0").unwrap();
+        assert_eq!(
+            validate_nobles(&nobles, &cards).unwrap_err().to_string(),
+            "Noble on row 2 has implausible VP: 200"
+        );
+    }
+
+    #[test]
+    fn random_playout() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut gs = GameState::init_seeded(3, 3).unwrap();
+        let mut num_turns = 0;
+        while !gs.apply_random_action(&mut rng).unwrap().0 {
+            num_turns += 1;
+            assert!(num_turns < 10000, "Random game did not finish");
+        }
+        assert!(gs.is_finished());
+        assert!(gs.apply_random_action(&mut rng).is_err());
+    }
+
+    #[test]
+    fn market_stays_full() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut gs = GameState::init_seeded(4, 5).unwrap();
+        // Nearly empty piles, so they run dry during the game.
+        for pile in gs.piles.iter_mut() {
+            pile.truncate(2);
+        }
+        for _ in 0..200 {
+            if gs.apply_random_action(&mut rng).unwrap().0 {
+                break;
+            }
+            for (row, pile) in gs.market.iter().zip(gs.piles.iter()) {
+                assert_eq!(row.len(), (row.len() + pile.len()).min(4));
+            }
+        }
+        assert!(gs.piles.iter().all(|p| p.is_empty()));
+    }
+
+    #[test]
+    fn noble_ties() {
+        let mut gs = GameState::init(2).unwrap();
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 0, 0, 0, 0],
+        };
+        // Player 0: 15 VP from 4 cards and two nobles.
+        for _ in 0..4 {
+            give_card(&mut gs.players[0], Color::Red);
+        }
+        gs.players[0].nobles = vec![noble.clone(), noble.clone()];
+        gs.players[0].vp_history.push((10, 15));
+        // Player 1: 15 VP from 3 cards and no nobles.
+        for _ in 0..3 {
+            give_card(&mut gs.players[1], Color::Blue);
+        }
+        gs.players[1].vp_history.push((10, 15));
+        gs.finished = true;
+        // Fewer cards wins, regardless of nobles.
+        assert_eq!(gs.winner(), Some(1));
+
+        assert_eq!(gs.winners(), vec![1]);
+
+        // With equal card counts, the extra nobles still don't matter, and
+        // the win is shared.
+        give_card(&mut gs.players[1], Color::Blue);
+        assert_eq!(gs.winners(), vec![0, 1]);
+        assert_eq!(gs.winner(), Some(0));
+        gs.players[1].nobles = vec![noble.clone(), noble.clone(), noble];
+        assert_eq!(gs.winners(), vec![0, 1]);
+    }
+
+    #[test]
+    fn legal_actions_for_other_players() {
+        let mut gs = GameState::init(2).unwrap();
+        let me = gs.curr_player_idx;
+        let other = 1 - me;
+        assert_eq!(gs.legal_actions_for(me), gs.valid_actions());
+        // The other player can afford a card, but the current player can't.
+        gs.players[other].tokens = [4, 4, 4, 4, 4, 0];
+        assert_eq!(gs.legal_actions_for(me), gs.valid_actions());
+        assert!(gs
+            .legal_actions_for(other)
+            .iter()
+            .any(|a| matches!(a, Action::BuyCard(_))));
+    }
+
+    #[test]
+    fn max_affordable_card() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.market = [vec![], vec![], vec![]];
+        assert!(gs.max_affordable_card().is_none());
+        let market_card = Card {
+            id: 0,
+            level: 2,
+            color: Color::Red,
+            vp: 2,
+            cost: [0, 0, 0, 2, 0],
+        };
+        let reserved_card = Card {
+            id: 0,
+            level: 3,
+            color: Color::Blue,
+            vp: 3,
+            cost: [0, 2, 0, 0, 0],
+        };
+        let too_expensive = Card {
+            id: 0,
+            level: 3,
+            color: Color::Green,
+            vp: 5,
+            cost: [0, 0, 7, 0, 0],
+        };
+        gs.market[1].push(market_card.clone());
+        gs.market[2].push(too_expensive);
+        gs.players[idx].reserve(reserved_card.clone(), &mut 0, Ruleset::MAX_TOKENS);
+        gs.players[idx].tokens = [0, 2, 0, 2, 0, 0];
+        assert_eq!(
+            gs.max_affordable_card(),
+            Some((CardLocation::Reserve(0), &reserved_card))
+        );
+        // Without the reserved card's tokens, the market card is the best.
+        gs.players[idx].tokens = [0, 0, 0, 2, 0, 0];
+        assert_eq!(
+            gs.max_affordable_card(),
+            Some((CardLocation::Market(2, 0), &market_card))
+        );
+    }
+
+    #[test]
+    fn bank_can_support() {
+        let mut gs = GameState::init(2).unwrap();
+        let take = Action::take_different(&[Color::White, Color::Green, Color::Red]);
+        assert!(gs.bank_can_support(&take));
+        gs.bank[Color::Green as usize] = 0;
+        assert!(!gs.bank_can_support(&take));
+        assert!(gs.bank_can_support(&Action::take_different(&[Color::White, Color::Red])));
+        gs.bank[Color::Red as usize] = 3;
+        assert!(!gs.bank_can_support(&Action::TakeSameColorTokens(Color::Red)));
+        assert!(!gs.bank_can_support(&Action::TakeSameColorTokens(Color::Gold)));
+        assert!(gs.bank_can_support(&Action::ReserveCard(CardLocation::Pile(1))));
+    }
+
+    #[test]
+    fn stacked_pile_draw_order() {
+        let mut gs = GameState::init(2).unwrap();
+        let draws: Vec<Card> = (1..=3)
+            .map(|vp| Card {
+                id: 0,
+                level: 1,
+                color: Color::Black,
+                vp,
+                cost: [1, 1, 1, 1, 0],
+            })
+            .collect();
+        gs.stack_pile(1, draws.clone()).unwrap();
+        assert!(gs.stack_pile(4, vec![]).is_err());
+        for expected in &draws {
+            gs.take_turn(&Action::ReserveCard(CardLocation::Market(1, 0)))
+                .unwrap();
+            assert_eq!(&gs.market[0][0], expected);
+        }
+        assert!(gs.piles[0].is_empty());
+    }
+
+    #[test]
+    fn views_hide_seed() {
+        let gs = GameState::init_seeded(2, 42).unwrap();
+        assert_eq!(gs.redacted_for(0).seed(), None);
+        let parse = |s: String| serde_json::from_str::<serde_json::Value>(&s).unwrap();
+        for audience in [
+            Audience::Player(0),
+            Audience::Player(1),
+            Audience::Spectator,
+        ] {
+            let view = parse(gs.serialize_for(audience).unwrap());
+            assert!(view.get("seed").is_none());
+        }
+        let view = parse(gs.serialize_for(Audience::Full).unwrap());
+        assert_eq!(view["seed"], 42);
+    }
+
+    #[test]
+    fn serialize_for_audiences() {
+        let mut gs = GameState::init(2).unwrap();
+        let me = gs.curr_player_idx;
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        let opponent = gs.curr_player_idx;
+        let parse = |s: String| serde_json::from_str::<serde_json::Value>(&s).unwrap();
+
+        let view = parse(gs.serialize_for(Audience::Player(opponent)).unwrap());
+        // A card reserved from the market was seen by everyone.
+        assert_eq!(view["players"][me]["reserved"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            view["players"][me]["hidden_reserved"],
+            serde_json::json!([])
+        );
+        assert!(view["players"][opponent]["reserved"].is_array());
+        let player = &gs.players[opponent];
+        let missing = player.missing_tokens(&gs.market[2][3]);
+        let hint = &view["missing_tokens"]["market"][2][3];
+        assert_eq!(hint["tokens"], serde_json::json!(missing));
+        assert_eq!(hint["gold"], missing.iter().sum::<u8>());
+        assert_eq!(view["missing_tokens"]["reserved"], serde_json::json!([]));
+        let counts = serde_json::json!(gs.pile_counts());
+        assert_eq!(view["pile_counts"], counts);
+        assert!(gs.serialize_for(Audience::Player(2)).is_err());
+
+        let view = parse(gs.serialize_for(Audience::Player(me)).unwrap());
+        let missing = gs.players[me].missing_tokens(gs.players[me].peek_reserved(0).unwrap());
+        let hint = &view["missing_tokens"]["reserved"][0];
+        assert_eq!(hint["tokens"], serde_json::json!(missing));
+        assert_eq!(hint["gold"], missing.iter().sum::<u8>());
+
+        let view = parse(gs.serialize_for(Audience::Spectator).unwrap());
+        assert!(view.get("missing_tokens").is_none());
+        assert_eq!(view["players"][me]["reserved"].as_array().unwrap().len(), 1);
+        assert_eq!(view["pile_counts"], counts);
+
+        let view = parse(gs.serialize_for(Audience::Full).unwrap());
+        assert_eq!(view["players"][me]["reserved"].as_array().unwrap().len(), 1);
+        assert_eq!(view["pile_counts"], counts);
+
+        // Only the full view reveals the piles themselves.
+        assert_eq!(view["piles"], serde_json::to_value(&gs.piles).unwrap());
+        for audience in [Audience::Player(me), Audience::Spectator] {
+            let view = parse(gs.serialize_for(audience).unwrap());
+            assert!(view.get("piles").is_none());
+        }
+    }
+
+    #[test]
+    fn init_player_count() {
+        for num_players in 2..=4 {
+            let gs = GameState::init(num_players).unwrap();
+            assert_eq!(gs.players.len(), num_players);
+            assert_eq!(gs.nobles.len(), num_players + 1);
+        }
+        for num_players in [0, 1, 5, 9] {
+            let err = GameState::init(num_players).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid number of players: {}, must be 2 to 4", num_players)
+            );
+        }
+    }
+
+    #[test]
+    fn init_with_small_deck() {
+        let card = |level| Card {
+            id: 0,
+            level,
+            color: Color::White,
+            vp: 0,
+            cost: [0, 1, 0, 0, 0],
+        };
+        let mut cards: Vec<Card> = [1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3]
+            .into_iter()
+            .map(card)
+            .collect();
+        let gs = GameState::init_with_deck(2, 0, cards.clone(), vec![]).unwrap();
+        assert!(gs.piles.iter().all(|p| p.is_empty()));
+        assert!(gs.market.iter().all(|row| row.len() == 4));
+
+        cards.remove(0);
+        let err = GameState::init_with_deck(2, 0, cards, vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deck has only 3 level 1 cards, but at least 4 are needed"
+        );
+    }
+
+    #[test]
+    fn player_scalar_accessors() {
+        let mut gs = GameState::init(3).unwrap();
+        gs.players[1].tokens = [1, 2, 0, 0, 0, 1];
+        give_card(&mut gs.players[2], Color::Red);
+        for idx in 0..3 {
+            assert_eq!(gs.player_vp(idx), Some(gs.players[idx].vp()));
+            assert_eq!(
+                gs.player_token_count(idx),
+                Some(gs.players[idx].num_tokens())
+            );
+        }
+        assert_eq!(gs.player_token_count(1), Some(4));
+        assert_eq!(gs.player_vp(3), None);
+        assert_eq!(gs.player_token_count(3), None);
+        assert_eq!(gs.player_discounts(), vec![[0; 5], [0; 5], [0, 0, 0, 1, 0]]);
+    }
+
+    #[test]
+    fn card_and_noble_vp() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        give_card(&mut gs.players[idx], Color::White);
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        gs.nobles = vec![Noble {
+            id: 0,
+            vp: 3,
+            cost: [2, 0, 0, 0, 0],
+        }];
+        gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        let p = &gs.players[idx];
+        assert_eq!(p.card_vp(), 1);
+        assert_eq!(p.noble_vp(), 3);
+        assert_eq!(p.card_vp() + p.noble_vp(), p.vp());
+        assert_eq!(gs.noble_vp_total(), 3);
+    }
+
+    #[test]
+    fn scripted_human_vs_ai() {
+        let mut gs = GameState::init_seeded(2, 11).unwrap();
+        let human = gs.curr_player_idx;
+        let mut agents: Vec<Option<Box<dyn Agent + Send>>> = vec![None, None];
+        agents[1 - human] = Some(Box::new(NoisyAgent::seeded(RandomAgent, 0.0, 11)));
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Red,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        let script = [
+            r#"{"BuyCard": {"Market": [1, 0]}}"#,
+            r#"{"TakeDifferentColorTokens": ["white", "blue", "green"]}"#,
+            r#"{"TakeDifferentColorTokens": []}"#,
+        ];
+        let played = gs.play_script(&agents, &script).unwrap();
+        let seats: Vec<usize> = played.iter().map(|(i, _)| *i).collect();
+        assert_eq!(
+            seats,
+            [human, 1 - human, human, 1 - human, human, 1 - human]
+        );
+        assert_eq!(played[4].1, Action::Pass);
+        assert_eq!(gs.players[human].vp(), 1);
+        assert_eq!(gs.players[human].num_tokens(), 3);
+        assert!(gs.play_script(&agents, &["not json"]).is_err());
+    }
+
+    #[test]
+    fn noble_race_plan() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        give_card(&mut gs.players[idx], Color::Green);
+        let card = |color, cost| Card {
+            id: 0,
+            level: 1,
+            color,
+            vp: 0,
+            cost,
+        };
+        gs.market = [
+            vec![
+                card(Color::Green, [0, 0, 0, 3, 0]),
+                card(Color::Red, [1, 0, 0, 0, 0]),
+                card(Color::Green, [0, 1, 0, 0, 0]),
+                card(Color::Blue, [0, 0, 0, 0, 1]),
+            ],
+            vec![card(Color::Green, [0, 0, 0, 0, 1])],
+            vec![],
+        ];
+        gs.players[idx].tokens = [1, 1, 0, 0, 0, 0];
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 0, 3, 1, 0],
+        };
+        // Two more green (the two cheapest), then one red.
+        assert_eq!(
+            gs.tokens_needed_for_noble_race(&noble),
+            Some(vec![
+                CardLocation::Market(1, 2),
+                CardLocation::Market(2, 0),
+                CardLocation::Market(1, 1),
+            ])
+        );
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 0, 0, 2, 0],
+        };
+        assert_eq!(gs.tokens_needed_for_noble_race(&noble), None);
+    }
+
+    #[test]
+    fn render_diff() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        let prev = gs.clone();
+        gs.take_turn(&Action::take_different(&[
+            Color::Red,
+            Color::White,
+            Color::Black,
+        ]))
+        .unwrap();
+        assert_eq!(
+            gs.render_diff(&prev),
+            format!("Player {} took 1W 1R 1K", idx)
+        );
+
+        let prev = gs.clone();
+        let card = gs.market[0][1].clone();
+        let refill = gs
+            .market_refill_preview(&CardLocation::Market(1, 1))
+            .cloned();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(1, 1)))
+            .unwrap();
+        let diff = gs.render_diff(&prev);
+        assert!(diff.contains(&format!("Player {} reserved {}", 1 - idx, card)));
+        assert!(diff.contains(&format!("Player {} took 1*", 1 - idx)));
+        if let Some(refill) = refill {
+            assert!(diff.contains(&format!("New in market: {}", refill)));
+        }
+    }
+
+    #[test]
+    fn legal_same_color_takes() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        let from_actions = |gs: &GameState| -> Vec<Color> {
+            gs.valid_actions()
+                .into_iter()
+                .filter_map(|a| match a {
+                    Action::TakeSameColorTokens(c) => Some(c),
+                    _ => None,
+                })
+                .collect()
+        };
+        for bank_red in [3, 4, 5] {
+            for num_tokens in [7, 8, 9] {
+                gs.bank[Color::Red as usize] = bank_red;
+                gs.players[idx].tokens = [num_tokens, 0, 0, 0, 0, 0];
+                let takes = gs.legal_same_color_takes();
+                assert_eq!(takes, from_actions(&gs));
+                // The token limit doesn't matter, since excess is returned.
+                assert_eq!(takes.contains(&Color::Red), bank_red >= 4);
+                for color in takes {
+                    let mut next = gs.clone();
+                    assert!(next.take_turn(&Action::TakeSameColorTokens(color)).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn end_to_end_determinism() {
+        let seed = 17;
+        let agents: Vec<NoisyAgent<RandomAgent>> = (0..3)
+            .map(|i| NoisyAgent::seeded(RandomAgent, 0.0, seed + i))
+            .collect();
+        let mut played = GameState::init_seeded(3, seed).unwrap();
+        let mut log = Vec::new();
+        while !played.is_finished() {
+            let idx = played.curr_player_idx;
+            let action = agents[idx].choose_action(&played);
+            played.take_turn(&action).unwrap();
+            log.push((idx, action));
+            assert!(log.len() < 10000, "Random game did not finish");
+        }
+
+        let json = serde_json::to_string(&played).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+
+        let mut replayed = GameState::init_seeded(3, seed).unwrap();
+        for (idx, action) in log.iter() {
+            assert_eq!(replayed.curr_player_idx, *idx);
+            replayed.take_turn(action).unwrap();
+        }
+        assert!(replayed.is_finished());
+
+        for other in [&restored, &replayed] {
+            for (a, b) in played.players.iter().zip(other.players.iter()) {
+                assert_eq!(a.vp_history, b.vp_history);
+            }
+            assert_eq!(played.winner(), other.winner());
+        }
+        assert_eq!(serde_json::to_string(&replayed).unwrap(), json);
+    }
+
+    #[test]
+    fn seeded_init_is_reproducible() {
+        let a = serde_json::to_string(&GameState::init_seeded(4, 42).unwrap()).unwrap();
+        let b = serde_json::to_string(&GameState::init_seeded(4, 42).unwrap()).unwrap();
+        assert_eq!(a, b);
+        let restored: GameState = serde_json::from_str(&a).unwrap();
+        assert_eq!(restored.seed(), Some(42));
+        let redealt = GameState::init_seeded(4, restored.seed().unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&redealt).unwrap(), a);
+        assert_ne!(
+            serde_json::to_string(&GameState::init_seeded(4, 43).unwrap()).unwrap(),
+            a
+        );
+    }
+
+    #[test]
+    fn choose_between_nobles() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        give_card(&mut gs.players[idx], Color::Green);
+        gs.nobles = vec![
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 2, 0, 0],
+            },
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 0, 0, 4],
+            },
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 1, 1, 0],
+            },
+        ];
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::Green,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        give_card(&mut gs.players[idx], Color::Red);
+        // Buying the green card qualifies for both the first and last nobles.
+        gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        assert_eq!(gs.curr_player_idx, idx);
+        assert_eq!(gs.pending(), Some(PendingChoice::AcquireNoble));
+        assert_eq!(
+            gs.valid_actions(),
+            vec![Action::AcquireNoble(0), Action::AcquireNoble(2)]
+        );
+        // The card's VP is already counted.
+        assert_eq!(gs.players[idx].vp(), 1);
+        assert_eq!(
+            gs.take_turn(&Action::Pass).unwrap_err().to_string(),
+            "Must choose a noble first"
+        );
+        assert_eq!(
+            gs.take_turn(&Action::AcquireNoble(1))
+                .unwrap_err()
+                .to_string(),
+            "Cannot acquire that noble"
+        );
+        gs.take_turn(&Action::AcquireNoble(2)).unwrap();
+        assert_eq!(gs.players[idx].vp(), 4);
+        assert_eq!(gs.players[idx].noble_vp(), 3);
+        assert_eq!(gs.nobles.len(), 2);
+        assert_eq!(gs.pending(), None);
+        assert_ne!(gs.curr_player_idx, idx);
+        assert_eq!(
+            gs.take_turn(&Action::AcquireNoble(0))
+                .unwrap_err()
+                .to_string(),
+            "No noble to choose"
+        );
+    }
+
+    #[test]
+    fn validate_action_errors() {
+        let mut gs = GameState::init(2).unwrap();
+        let before = serde_json::to_string(&gs).unwrap();
+        let cases = [
+            (
+                Action::TakeDifferentColorTokens(vec![Color::White; 4]),
+                ActionError::TooManyTokens,
+            ),
+            (
+                Action::take_different(&[Color::White, Color::Gold]),
+                ActionError::GoldNotTakeable,
+            ),
+            (
+                Action::TakeDifferentColorTokens(vec![Color::Red, Color::Red]),
+                ActionError::DuplicateColor,
+            ),
+            (
+                Action::TakeSameColorTokens(Color::Gold),
+                ActionError::GoldNotTakeable,
+            ),
+            (
+                Action::ReserveCard(CardLocation::Reserve(0)),
+                ActionError::AlreadyReserved,
+            ),
+            (
+                Action::BuyCard(CardLocation::Pile(1)),
+                ActionError::BuyFromPile,
+            ),
+            (
+                Action::BuyCard(CardLocation::Market(1, 9)),
+                ActionError::InvalidLocation("Invalid market index".into()),
+            ),
+            (
+                Action::ReturnTokens(vec![Color::Red]),
+                ActionError::NothingToReturn,
+            ),
+            (Action::AcquireNoble(0), ActionError::NoNobleToChoose),
+        ];
+        for (action, err) in cases {
+            assert_eq!(gs.validate_action(&action), Err(err.clone()));
+            assert_eq!(
+                gs.take_turn(&action).unwrap_err().to_string(),
+                err.to_string()
+            );
+        }
+        // Nothing was applied.
+        assert_eq!(serde_json::to_string(&gs).unwrap(), before);
+
+        gs.bank[Color::Red as usize] = 3;
+        assert_eq!(
+            gs.validate_action(&Action::TakeSameColorTokens(Color::Red)),
+            Err(ActionError::InsufficientBank)
+        );
+        gs.market[0][0].cost = [7, 7, 7, 7, 7];
+        assert_eq!(
+            gs.validate_action(&Action::BuyCard(CardLocation::Market(1, 0))),
+            Err(ActionError::CannotAfford)
+        );
+        gs.piles[2].clear();
+        assert_eq!(
+            gs.validate_action(&Action::ReserveCard(CardLocation::Pile(3))),
+            Err(ActionError::InvalidLocation("No cards left".into()))
+        );
+        assert_eq!(gs.validate_action(&Action::Pass), Ok(()));
+    }
+
+    #[test]
+    fn replay_history() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut gs = GameState::init_seeded(3, 8).unwrap();
+        for _ in 0..30 {
+            let idx = gs.curr_player_idx;
+            let (_, action) = gs.apply_random_action(&mut rng).unwrap();
+            assert_eq!(gs.history().last(), Some(&(idx, action)));
+        }
+        assert_eq!(gs.history().len(), 30);
+        // A rejected action isn't recorded.
+        assert!(gs.take_turn(&Action::AcquireNoble(0)).is_err());
+        assert_eq!(gs.history().len(), 30);
+
+        let restored: GameState =
+            serde_json::from_str(&serde_json::to_string(&gs).unwrap()).unwrap();
+        assert_eq!(restored.history(), gs.history());
+        let replayed = GameState::replay(3, 8, restored.history()).unwrap();
+        let scores = |g: &GameState| g.players.iter().map(|p| p.vp()).collect::<Vec<_>>();
+        assert_eq!(scores(&replayed), scores(&gs));
+        assert_eq!(replayed.history(), gs.history());
+
+        let mut wrong_seat = gs.history().to_vec();
+        wrong_seat[0].0 = (wrong_seat[0].0 + 1) % 3;
+        assert!(GameState::replay(3, 8, &wrong_seat).is_err());
+    }
+
+    #[test]
+    fn restored_game_keeps_piles() {
+        let gs = GameState::init_seeded(2, 21).unwrap();
+        let mut restored: GameState =
+            serde_json::from_str(&serde_json::to_string(&gs).unwrap()).unwrap();
+        assert_eq!(restored.piles, gs.piles);
+        let idx = restored.curr_player_idx;
+        let top = restored.piles[1].last().unwrap().clone();
+        restored
+            .take_turn(&Action::ReserveCard(CardLocation::Pile(2)))
+            .unwrap();
+        assert_eq!(restored.players[idx].peek_reserved(0), Some(&top));
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn bytes_round_trip() {
+        let mut gs = GameState::init_seeded(3, 8).unwrap();
+        for _ in 0..6 {
+            let action = gs.valid_actions()[0].clone();
+            gs.take_turn(&action).unwrap();
+        }
+        let bytes = gs.to_bytes().unwrap();
+        let restored = GameState::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.piles, gs.piles);
+        assert_eq!(restored.history, gs.history);
+        let json = serde_json::to_string(&gs).unwrap();
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+        assert!(bytes.len() * 3 < json.len());
+        assert!(GameState::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_count_valid_actions() {
+        let mut gs = GameState::init_seeded(2, 5).unwrap();
+        assert_eq!(gs.metrics(), Metrics::default());
+        let actions = gs.valid_actions();
+        gs.valid_actions();
+        assert_eq!(gs.metrics().valid_actions_calls, 2);
+        assert_eq!(gs.metrics().max_actions, actions.len());
+        gs.take_turn(&actions[0]).unwrap();
+        let clone = gs.clone();
+        clone.valid_actions();
+        assert_eq!(clone.metrics().valid_actions_calls, 3);
+        assert_eq!(gs.metrics().valid_actions_calls, 2);
+    }
+
+    #[test]
+    fn redacted_reserves() {
+        let mut gs = GameState::init(2).unwrap();
+        let p2 = gs.curr_player_idx;
+        let p1 = 1 - p2;
+        let seen = gs.market[2][0].clone();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(3, 0)))
+            .unwrap();
+        let mine = gs.piles[1].last().unwrap().clone();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Pile(2)))
+            .unwrap();
+        let blind = gs.piles[0].last().unwrap().clone();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Pile(1)))
+            .unwrap();
+
+        // Only the blind reserve is hidden from the opponent.
+        let view = gs.redacted_for(p1);
+        assert_eq!(view.players[p2].peek_reserved(0), Some(&seen));
+        assert!(view.players[p2].peek_reserved(1).is_none());
+        assert_eq!(view.players[p2].hidden_reserved(), &[blind.level]);
+        assert_eq!(view.players[p2].num_reserved(), 2);
+        assert!(view.players[p1].hidden_reserved().is_empty());
+        // Their own blind reserve stays face up for them.
+        assert_eq!(view.players[p1].peek_reserved(0), Some(&mine));
+        assert!(view.piles.iter().all(|p| p.is_empty()));
+        // The real game is untouched.
+        assert_eq!(gs.players[p2].peek_reserved(1), Some(&blind));
+    }
+
+    #[test]
+    fn configured_target_vp() {
+        let agent = create_agent(2);
+        for target_vp in [10, 20] {
+            let config = GameConfig {
+                num_players: 2,
+                target_vp,
+                seed: Some(target_vp as u64),
+                bank: None,
+            };
+            let mut gs = GameState::init_with_config(&config).unwrap();
+            assert_eq!(gs.seed(), Some(target_vp as u64));
+            let json = serde_json::to_string(&gs).unwrap();
+            let restored: GameState = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.target_vp(), target_vp);
+
+            gs.simulate_to_end_with(agent.as_ref()).unwrap();
+            assert_eq!(gs.outcome(), Some(GameOutcome::VictoryThreshold));
+            // The game ends in the first round that anyone reaches the target.
+            let rounds_at_target: Vec<u16> = gs
+                .players
+                .iter()
+                .flat_map(|p| p.vp_history.iter())
+                .filter(|(_, vp)| *vp >= target_vp)
+                .map(|(round, _)| *round)
+                .collect();
+            assert!(!rounds_at_target.is_empty());
+            assert!(rounds_at_target.iter().all(|&r| r == gs.round()));
+        }
+        let config = GameConfig {
+            target_vp: 0,
+            ..GameConfig::new(2)
+        };
+        assert!(GameState::init_with_config(&config).is_err());
+    }
+
+    #[test]
+    fn configured_bank() {
+        let config = GameConfig {
+            bank: Some([1, 1, 0, 0, 0, 0]),
+            ..GameConfig::new(2)
+        };
+        let gs = GameState::init_with_config(&config).unwrap();
+        assert_eq!(gs.bank, [1, 1, 0, 0, 0, 0]);
+        let takes: Vec<Action> = gs
+            .valid_actions()
+            .into_iter()
+            .filter(|a| {
+                matches!(
+                    a,
+                    Action::TakeDifferentColorTokens(_) | Action::TakeSameColorTokens(_)
+                )
+            })
+            .collect();
+        assert_eq!(
+            takes,
+            vec![Action::TakeDifferentColorTokens(vec![
+                Color::White,
+                Color::Blue
+            ])]
+        );
+
+        let config = GameConfig {
+            bank: Some([7, 7, 7, 7, 7, 6]),
+            ..GameConfig::new(2)
+        };
+        assert!(GameState::init_with_config(&config).is_err());
+    }
+
+    #[test]
+    fn custom_deck_endgame() {
+        // Every card is free and worth 5 VP, so the game ends after 3 rounds.
+        let csv: String = std::iter::once("level,color,vp,cost\n".to_string())
+            .chain((1..=3).flat_map(|level| {
+                ["white", "blue", "green", "red"]
+                    .iter()
+                    .map(move |color| format!("{},{},5,0,0,0,0,0\n", level, color))
+            }))
+            .collect();
+        let cards = load_from_csv::<Card>(&csv).unwrap();
+        let nobles = load_from_csv::<Noble>("vp,cost\n3,3,3,0,0,0").unwrap();
+        assert_eq!(cards.len(), 12);
+        let mut gs = GameState::init_with_deck(2, 5, cards, nobles).unwrap();
+        assert_eq!(gs.nobles.len(), 1);
+        let mut turns = 0;
+        while !gs.is_finished() {
+            let level = gs.market.iter().position(|row| !row.is_empty()).unwrap();
+            gs.take_turn(&Action::BuyCard(CardLocation::Market(level + 1, 0)))
+                .unwrap();
+            turns += 1;
+        }
+        assert_eq!(turns, 6);
+        assert_eq!(gs.outcome(), Some(GameOutcome::VictoryThreshold));
+        assert!(gs.players.iter().all(|p| p.vp() == 15));
+    }
+
+    #[test]
+    fn buy_with_explicit_payment() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 1,
+            cost: [0, 2, 0, 0, 0],
+        };
+        gs.players[idx].tokens = [0, 2, 0, 0, 0, 1];
+        let loc = CardLocation::Market(1, 0);
+        let bad = Action::BuyCardWithPayment(loc.clone(), [0, 0, 0, 0, 0, 2]);
+        assert_eq!(gs.validate_action(&bad), Err(ActionError::InvalidPayment));
+        assert_eq!(
+            gs.validate_action(&Action::BuyCardWithPayment(CardLocation::Pile(1), [0; 6])),
+            Err(ActionError::BuyFromPile)
+        );
+        // Spend the gold even though a second blue token is on hand.
+        let bank_before = gs.bank;
+        gs.take_turn(&Action::BuyCardWithPayment(loc, [0, 1, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(gs.players[idx].tokens, [0, 1, 0, 0, 0, 0]);
+        assert_eq!(gs.bank[1], bank_before[1] + 1);
+        assert_eq!(gs.bank[5], bank_before[5] + 1);
+        assert_eq!(gs.players[idx].vp(), 1);
+        assert_eq!(gs.history().len(), 1);
+    }
+
+    #[test]
+    fn action_outcomes() {
+        let gs = GameState::init_seeded(2, 3).unwrap();
+        let take = Action::take_different(&[Color::White, Color::Blue, Color::Red]);
+        assert_eq!(gs.outcomes(&take).unwrap().len(), 1);
+
+        let reserve = Action::ReserveCard(CardLocation::Market(2, 0));
+        let outcomes = gs.outcomes(&reserve).unwrap();
+        let total: f64 = outcomes.iter().map(|(p, _)| p).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        // Each outcome refills the reserved slot with a different card.
+        let refills: Vec<&Card> = outcomes
+            .iter()
+            .map(|(_, next)| &next.market[1][0])
+            .collect();
+        for (i, card) in refills.iter().enumerate() {
+            assert!(!refills[..i].contains(card));
+        }
+        assert!(gs
+            .outcomes(&Action::BuyCard(CardLocation::Pile(1)))
+            .is_err());
+    }
+
+    #[test]
+    fn outcomes_include_hidden_reserves() {
+        let mut gs = GameState::init_seeded(2, 3).unwrap();
+        let reserve = Action::ReserveCard(CardLocation::Market(2, 0));
+        gs.take_turn(&reserve).unwrap();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        let blind = gs.piles[1].last().unwrap().clone();
+        gs.take_turn(&Action::ReserveCard(CardLocation::Pile(2)))
+            .unwrap();
+        let me = gs.curr_player_idx;
+        // The opponent's level 2 reserve from the market was seen, so only
+        // the blind one could still be in play.
+        let outcomes = gs.outcomes(&reserve).unwrap();
+        assert_eq!(outcomes.len(), gs.piles[1].len() + 1);
+        let total: f64 = outcomes.iter().map(|(p, _)| p).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        // The opponent's blind reserve might be the refill, in which case
+        // they hold a card from the pile instead.
+        let (_, next) = outcomes
+            .iter()
+            .find(|(_, next)| next.market[1][0] == blind)
+            .unwrap();
+        let theirs = next.players[1 - me].peek_reserved(1).unwrap();
+        assert_ne!(theirs, &blind);
+        assert!(gs.piles[1].contains(theirs));
+        assert_eq!(next.piles[1].len(), gs.piles[1].len() - 1);
+    }
+
+    #[test]
+    fn action_events() {
+        let mut gs = GameState::init(2).unwrap();
+        gs.curr_player_idx = 0;
+        gs.first_player = 0;
+        let mut events = Vec::new();
+        let action = Action::ReserveCard(CardLocation::Pile(1));
+        gs.process_action_events(&action, &mut |e| events.push(e.clone()))
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![GameEvent::TurnTaken {
+                player_idx: 0,
+                action,
+                new_scores: vec![0, 0],
+                gold_granted: true,
+            }]
+        );
+        let json = serde_json::to_string(&events[0]).unwrap();
+        assert_eq!(serde_json::from_str::<GameEvent>(&json).unwrap(), events[0]);
+
+        // A winning buy that also earns a noble.
+        events.clear();
+        let idx = gs.curr_player_idx;
+        gs.players[idx].vp_history.push((1, 12));
+        give_card(&mut gs.players[idx], Color::White);
+        gs.market[0][0] = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 0,
+            cost: [0, 0, 0, 0, 0],
+        };
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [2, 0, 0, 0, 0],
+        };
+        gs.nobles = vec![noble.clone()];
+        let buy = Action::BuyCard(CardLocation::Market(1, 0));
+        assert!(gs
+            .process_action_events(&buy, &mut |e| events.push(e.clone()))
+            .unwrap());
+        assert_eq!(
+            events,
+            vec![
+                GameEvent::TurnTaken {
+                    player_idx: 1,
+                    action: buy,
+                    new_scores: vec![0, 15],
+                    gold_granted: false,
+                },
+                GameEvent::NobleAcquired {
+                    player_idx: 1,
+                    noble,
+                },
+                GameEvent::GameOver { winners: vec![1] },
+            ]
+        );
+    }
+
+    #[test]
+    fn turn_outcome_reports_gold() {
+        let reserve = Action::ReserveCard(CardLocation::Market(1, 0));
+        let mut gs = GameState::init(2).unwrap();
+        assert!(gs.take_turn(&reserve).unwrap().gold_granted);
+        // No gold left in the bank.
+        gs.bank[5] = 0;
+        assert!(!gs.take_turn(&reserve).unwrap().gold_granted);
+        // At the token cap.
+        gs.bank[5] = 5;
+        let idx = gs.curr_player_idx;
+        gs.players[idx].tokens = [2, 2, 2, 2, 1, 1];
+        let outcome = gs.take_turn(&reserve).unwrap();
+        assert!(!outcome.gold_granted);
+        assert_eq!(gs.players[idx].tokens[5], 1);
+    }
+
+    #[test]
+    fn turn_outcome_reports_noble() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        give_card(&mut gs.players[idx], Color::Green);
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::Green,
+            vp: 1,
+            cost: [0, 0, 0, 0, 0],
+        };
+        gs.market[0][0] = card.clone();
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 0, 2, 0, 0],
+        };
+        gs.nobles = vec![noble.clone()];
+        let take = Action::take_different(&[Color::White, Color::Blue, Color::Red]);
+        assert_eq!(gs.take_turn(&take).unwrap(), TurnOutcome::default());
+        gs.curr_player_idx = idx;
+        let outcome = gs
+            .take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        assert_eq!(
+            outcome,
+            TurnOutcome {
+                is_over: false,
+                noble: Some(noble),
+                vp_gained: 4,
+                card: Some(card),
+                gold_granted: false,
+            }
+        );
+    }
+
+    #[test]
+    fn final_round_completes() {
+        for first in [0, 2] {
+            let mut gs = GameState::init(4).unwrap();
+            gs.curr_player_idx = first;
+            gs.first_player = first;
+            gs.players[first].vp_history.push((1, 14));
+            gs.market[0][0] = Card {
+                id: 0,
+                level: 1,
+                color: Color::White,
+                vp: 1,
+                cost: [0, 0, 0, 0, 0],
+            };
+            assert!(
+                !gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+                    .unwrap()
+                    .is_over
+            );
+            assert_eq!(gs.player_vp(first), Some(15));
+            // The other three players still get their final turns, and the
+            // last of them overtakes the leader.
+            let last = (first + 3) % 4;
+            gs.players[last].vp_history.push((1, 16));
+            let take = Action::take_different(&[Color::White, Color::Blue, Color::Green]);
+            for i in 1..=3 {
+                assert!(gs.is_last_round());
+                assert!(!gs.is_finished());
+                assert_eq!(gs.curr_player_idx, (first + i) % 4);
+                assert_eq!(gs.take_turn(&take).unwrap().is_over, i == 3);
+            }
+            assert!(gs.is_finished());
+            // Play ends, but the current player is still a real seat.
+            assert_eq!(gs.curr_player_idx, first);
+            assert!(gs.take_turn(&take).is_err());
+            assert_eq!(gs.winner(), Some(last));
+            for idx in 0..4 {
+                assert_eq!(gs.history().iter().filter(|(i, _)| *i == idx).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn evaluate_after_buy() {
+        let mut gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        gs.players[idx].tokens = [3, 3, 0, 0, 0, 0];
+        gs.market[2][0] = Card {
+            id: 0,
+            level: 3,
+            color: Color::Black,
+            vp: 4,
+            cost: [3, 3, 0, 0, 0],
+        };
+        let before = gs.evaluate(idx);
+        let opponent_before = gs.evaluate(1 - idx);
+        gs.take_turn(&Action::BuyCard(CardLocation::Market(3, 0)))
+            .unwrap();
+        assert!(gs.evaluate(idx) > before + 3.0);
+        assert!(gs.evaluate(1 - idx) < opponent_before);
+    }
+
+    #[test]
+    fn encode_layout() {
+        for num_players in [2, 4] {
+            let mut gs = GameState::init(num_players).unwrap();
+            gs.players[1].tokens = [1, 2, 3, 4, 0, 0];
+            for idx in 0..num_players {
+                assert_eq!(gs.encode(idx).len(), GameState::encode_len());
+            }
+            // Player 1 comes first from their own perspective, and second
+            // from player 0's.
+            assert_eq!(gs.encode(1)[6..12], [1.0, 2.0, 3.0, 4.0, 0.0, 0.0]);
+            assert_eq!(gs.encode(0)[19..25], [1.0, 2.0, 3.0, 4.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn raised_token_cap() {
+        let gs = GameState::init_seeded(2, 11).unwrap();
+        let standard = gs.clone().with_ruleset(Ruleset::standard());
+        assert_eq!(gs.valid_actions(), standard.valid_actions());
+
+        let raised = Ruleset {
+            max_tokens: 12,
+            ..Ruleset::standard()
+        };
+        let take = Action::take_different(&[Color::Green, Color::Red, Color::Black]);
+        for (ruleset, pending) in [
+            (Ruleset::standard(), Some(PendingChoice::ReturnTokens(2))),
+            (raised.clone(), None),
+        ] {
+            let mut gs = gs.clone().with_ruleset(ruleset);
+            let idx = gs.curr_player_idx;
+            gs.players[idx].tokens = [3, 3, 3, 0, 0, 0];
+            gs.take_turn(&take).unwrap();
+            assert_eq!(gs.pending(), pending);
+            // Under the raised cap, the turn ends with all 12 tokens kept.
+            assert_eq!(gs.curr_player_idx == idx, pending.is_some());
+            assert_eq!(gs.players[idx].num_tokens(), 12);
+        }
+
+        // Reserving at 10 tokens still earns a gold token under the raised cap.
+        let mut gs = gs.with_ruleset(raised.clone());
+        let idx = gs.curr_player_idx;
+        gs.players[idx].tokens = [2, 2, 2, 2, 2, 0];
+        gs.take_turn(&Action::ReserveCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        assert_eq!(gs.players[idx].tokens[5], 1);
+        let json = serde_json::to_string(gs.ruleset()).unwrap();
+        assert_eq!(serde_json::from_str::<Ruleset>(&json).unwrap(), raised);
+        assert_eq!(
+            serde_json::from_str::<Ruleset>(r#"{"target_vp": 15}"#).unwrap(),
+            Ruleset::standard()
+        );
+    }
+
+    #[test]
+    fn same_color_take_token_boundaries() {
+        let gs = GameState::init(2).unwrap();
+        let idx = gs.curr_player_idx;
+        let take = Action::TakeSameColorTokens(Color::Red);
+        assert!(gs.can_take_same(Color::Red));
+        assert!(!gs.can_take_same(Color::Gold));
+        for (held, excess) in [(8, None), (9, Some(1))] {
+            let mut gs = gs.clone();
+            gs.players[idx].tokens = [held, 0, 0, 0, 0, 0];
+            assert!(gs.valid_actions().contains(&take));
+            assert_eq!(gs.validate_action(&take), Ok(()));
+            gs.take_turn(&take).unwrap();
+            assert_eq!(gs.pending(), excess.map(PendingChoice::ReturnTokens));
+        }
+        // With only 3 left in the bank, neither check allows it.
+        let mut gs = gs;
+        gs.bank[Color::Red as usize] = 3;
+        assert!(!gs.can_take_same(Color::Red));
+        assert!(!gs.valid_actions().contains(&take));
+        assert_eq!(
+            gs.validate_action(&take),
+            Err(ActionError::InsufficientBank)
         );
     }
 }
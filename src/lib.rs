@@ -1,4 +1,11 @@
 pub mod agent;
 pub mod data_types;
 pub mod game_state;
+pub mod log;
 mod player;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod stats;
+pub mod testing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,92 @@
+use crate::data_types::{Action, TurnOutcome};
+use crate::game_state::GameState;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+type DynError = Box<dyn std::error::Error>;
+
+// One move, as a training example for imitation learning.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Decision {
+    pub player_idx: usize,
+    // Turns completed before this move.
+    pub turn: u32,
+    // GameState::encode, from the moving player's point of view.
+    pub state: Vec<f32>,
+    pub legal_actions: Vec<Action>,
+    // Index of the chosen action in legal_actions.
+    pub action_idx: usize,
+    // VP the move gained.
+    pub reward: f32,
+}
+
+// Writes a Decision per move as JSON Lines. Each line is flushed as it's
+// written, so the log of an interrupted run is still usable.
+pub struct GameLogger<W: Write> {
+    out: W,
+}
+impl<W: Write> GameLogger<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+    // Plays the action like take_turn, logging it first if it succeeds.
+    pub fn play(&mut self, game: &mut GameState, action: &Action) -> Result<TurnOutcome, DynError> {
+        let player_idx = game.curr_player_idx;
+        let turn = game.turn_count();
+        let state = game.encode(player_idx);
+        let legal_actions = game.valid_actions();
+        let action_idx = legal_actions
+            .iter()
+            .position(|a| a == action)
+            .ok_or("Action is not legal")?;
+        let outcome = game.take_turn(action)?;
+        let decision = Decision {
+            player_idx,
+            turn,
+            state,
+            legal_actions,
+            action_idx,
+            reward: outcome.vp_gained as f32,
+        };
+        serde_json::to_writer(&mut self.out, &decision)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{CardLocation, Color};
+
+    #[test]
+    fn log_round_trip() {
+        let mut gs = GameState::init(2).unwrap();
+        let first = gs.curr_player_idx;
+        let mut logger = GameLogger::new(Vec::new());
+        let take = Action::take_different(&[Color::White, Color::Blue, Color::Red]);
+        let reserve = Action::ReserveCard(CardLocation::Market(1, 0));
+        logger.play(&mut gs, &take).unwrap();
+        let before = gs.clone();
+        logger.play(&mut gs, &reserve).unwrap();
+        // Illegal moves are rejected without being logged.
+        assert!(logger.play(&mut gs, &Action::AcquireNoble(0)).is_err());
+
+        let text = String::from_utf8(logger.out).unwrap();
+        let decisions: Vec<Decision> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(decisions.len(), 2);
+        assert_eq!(decisions[0].player_idx, first);
+        assert_eq!(decisions[0].legal_actions[decisions[0].action_idx], take);
+        let second = &decisions[1];
+        assert_eq!(second.player_idx, 1 - first);
+        assert_eq!(second.turn, 1);
+        assert_eq!(second.state, before.encode(1 - first));
+        assert_eq!(second.legal_actions, before.valid_actions());
+        assert_eq!(second.legal_actions[second.action_idx], reserve);
+        assert_eq!(second.reward, 0.0);
+    }
+}
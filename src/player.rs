@@ -1,4 +1,4 @@
-use crate::data_types::{Card, Noble};
+use crate::data_types::{ActionError, Affordability, Card, Color, Noble};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +9,14 @@ pub struct Player {
     owned: [Vec<u8>; 5],
     // Reserved cards
     reserved: Vec<Card>,
+    // Whether each reserved card was drawn blind from a pile, in step with
+    // `reserved`. Cards reserved from the market were seen by everyone.
+    #[serde(default)]
+    reserved_blind: Vec<bool>,
+    // Levels of reserved cards whose faces are hidden, in an opponent's view
+    // of this player. Always empty in the real game.
+    #[serde(default)]
+    hidden_reserved: Vec<usize>,
     // Acquired nobles
     pub nobles: Vec<Noble>,
     // VP history: [(round, vp)]
@@ -22,10 +30,29 @@ impl Player {
             tokens: [0, 0, 0, 0, 0, 0],
             owned: [Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()],
             reserved: Vec::new(),
+            reserved_blind: Vec::new(),
+            hidden_reserved: Vec::new(),
             nobles: Vec::new(),
             vp_history: vec![(0, 0)],
         }
     }
+    // Reconstructs a player from public information only: tokens, discounts,
+    // and VP. Owned cards are stand-ins worth 0 VP, and reserves are empty.
+    pub(crate) fn from_public(tokens: [u8; 6], discounts: [u8; 5], vp: u8, round: u16) -> Self {
+        let mut p = Self::default();
+        p.tokens = tokens;
+        for (owned, &n) in p.owned.iter_mut().zip(discounts.iter()) {
+            owned.resize(n as usize, 0);
+        }
+        if vp > 0 {
+            p.vp_history.push((round, vp));
+        }
+        p
+    }
+    // Number of purchased (development) cards. Nobles are not cards.
+    pub fn num_cards(&self) -> usize {
+        self.owned.iter().map(|c| c.len()).sum()
+    }
     pub fn num_tokens(&self) -> u8 {
         self.tokens.iter().sum()
     }
@@ -35,80 +62,273 @@ impl Player {
         if let Some((_, vp)) = self.vp_history.last() {
             return *vp;
         }
-        self.nobles.iter().map(|n| n.vp).sum::<u8>()
-            + self.owned.iter().map(|c| c.iter().sum::<u8>()).sum::<u8>()
+        self.card_vp() + self.noble_vp()
+    }
+    // VP from purchased cards and from nobles, which sum to vp().
+    // (Players rebuilt by from_public have no card VP to split out.)
+    pub fn card_vp(&self) -> u8 {
+        self.owned.iter().map(|c| c.iter().sum::<u8>()).sum()
+    }
+    pub fn noble_vp(&self) -> u8 {
+        self.nobles.iter().map(|n| n.vp).sum()
+    }
+    // Single-number positional estimate, for comparing players at a glance.
+    // Weighting (roughly "how many turns is this worth"):
+    //  - 100 per victory point
+    //  - 20 per owned card (permanent discount)
+    //  - 10 per VP on a reserved card, plus 5 per reserved card (hidden ones
+    //    included)
+    //  - 5 per colored token, 8 per gold token
+    pub fn net_worth(&self) -> i32 {
+        let discounts: i32 = self.owned.iter().map(|c| c.len() as i32).sum();
+        let reserved: i32 = self
+            .reserved
+            .iter()
+            .map(|c| 10 * c.vp as i32 + 5)
+            .sum::<i32>()
+            + 5 * self.hidden_reserved.len() as i32;
+        let tokens: i32 = self.tokens[0..5].iter().map(|&t| t as i32).sum();
+        100 * self.vp() as i32 + 20 * discounts + reserved + 5 * tokens + 8 * self.tokens[5] as i32
+    }
+    // Owned cards per color: [white, blue, green, red, black].
+    pub fn discounts(&self) -> [u8; 5] {
+        let mut discounts = [0; 5];
+        for (d, cards) in discounts.iter_mut().zip(self.owned.iter()) {
+            *d = cards.len() as u8;
+        }
+        discounts
     }
     pub fn purchasing_power(&self, include_tokens: bool) -> [u8; 5] {
-        let mut power: [u8; 5] = [0, 0, 0, 0, 0];
+        let mut power = self.discounts();
         if include_tokens {
-            power.copy_from_slice(&self.tokens[0..5]);
-        }
-        for (i, cards) in self.owned.iter().enumerate() {
-            power[i] += cards.len() as u8;
+            for (p, t) in power.iter_mut().zip(self.tokens.iter()) {
+                *p += t;
+            }
         }
         power
     }
+    // Number of owned cards of this color, each of which reduces the cost of
+    // future purchases by one token of that color.
+    pub fn discount_for(&self, color: Color) -> u8 {
+        self.owned.get(color as usize).map_or(0, |c| c.len() as u8)
+    }
     pub fn can_buy(&self, card: &Card) -> bool {
-        let power = self.purchasing_power(true);
-        let mut missing = 0u8;
+        self.affordability(card).can_afford()
+    }
+    pub fn affordability(&self, card: &Card) -> Affordability {
+        let mut missing = [0; 5];
+        for (i, &cost) in card.cost.iter().enumerate() {
+            let token_cost = cost.saturating_sub(self.discount_for(i.try_into().unwrap()));
+            missing[i] = token_cost.saturating_sub(self.tokens[i]);
+        }
+        let shortfall = missing.iter().sum::<u8>().saturating_sub(self.tokens[5]);
+        Affordability { missing, shortfall }
+    }
+    // Tokens of each color still needed for this card, after discounts and
+    // colored tokens. The sum is how much gold would cover it.
+    pub fn missing_tokens(&self, card: &Card) -> [u8; 5] {
+        self.affordability(card).missing
+    }
+    // Gold tokens that buying this card would use up, or None if it's
+    // unaffordable.
+    pub fn gold_needed(&self, card: &Card) -> Option<u8> {
+        let affordability = self.affordability(card);
+        affordability
+            .can_afford()
+            .then(|| affordability.missing.iter().sum())
+    }
+    // Tokens (including gold) that buying this card would return to the bank.
+    pub fn tokens_returned_by_buy(&self, card: &Card) -> [u8; 6] {
+        let mut payment = [0; 6];
         for (i, &cost) in card.cost.iter().enumerate() {
-            missing += cost.saturating_sub(power[i]);
+            let token_cost = cost.saturating_sub(self.discount_for(i.try_into().unwrap()));
+            // Spend colored tokens first, then cover the rest with gold.
+            payment[i] = token_cost.min(self.tokens[i]);
+            payment[5] += token_cost - payment[i];
         }
-        self.tokens[5] >= missing
+        payment
     }
     pub fn buy(&mut self, card: Card, bank: &mut [u8; 6]) {
-        let card_power = self.purchasing_power(false);
+        let payment = self.tokens_returned_by_buy(&card);
+        for (i, &n) in payment.iter().enumerate() {
+            self.remove_tokens(i.try_into().unwrap(), n, bank);
+        }
+        self.gain_card(&card);
+    }
+    // Adds a card as if bought, without paying for it.
+    pub(crate) fn gain_card(&mut self, card: &Card) {
+        self.owned[card.color as usize].push(card.vp);
+    }
+    // A payment is valid if the player holds it, it never overpays a color,
+    // and gold covers exactly what the colored tokens leave uncovered.
+    pub fn is_valid_payment(&self, card: &Card, payment: &[u8; 6]) -> bool {
+        if payment.iter().zip(self.tokens.iter()).any(|(p, t)| p > t) {
+            return false;
+        }
+        let mut shortfall = 0;
         for (i, &cost) in card.cost.iter().enumerate() {
-            let token_cost = cost.saturating_sub(card_power[i]);
-            let missing = token_cost.saturating_sub(self.tokens[i]);
-            if missing > 0 {
-                bank[5] += missing;
-                self.tokens[5] -= missing;
-                bank[i] += self.tokens[i];
-                self.tokens[i] = 0;
-            } else {
-                bank[i] += token_cost;
-                self.tokens[i] -= token_cost;
+            let token_cost = cost.saturating_sub(self.discount_for(i.try_into().unwrap()));
+            if payment[i] > token_cost {
+                return false;
             }
+            shortfall += token_cost - payment[i];
         }
-        self.owned[card.color as usize].push(card.vp);
+        payment[5] == shortfall
+    }
+    pub fn buy_with_payment(
+        &mut self,
+        card: Card,
+        payment: [u8; 6],
+        bank: &mut [u8; 6],
+    ) -> Result<(), ActionError> {
+        if !self.is_valid_payment(&card, &payment) {
+            return Err(ActionError::InvalidPayment);
+        }
+        for (i, &n) in payment.iter().enumerate() {
+            self.remove_tokens(i.try_into().unwrap(), n, bank);
+        }
+        self.gain_card(&card);
+        Ok(())
+    }
+    // Moves n tokens of a color from the bank to this player. The caller
+    // must have checked that the bank holds them.
+    pub(crate) fn add_tokens(&mut self, color: Color, n: u8, bank: &mut [u8; 6]) {
+        let c = color as usize;
+        debug_assert!(bank[c] >= n, "bank has only {} of {:?}", bank[c], color);
+        bank[c] -= n;
+        self.tokens[c] += n;
+    }
+    // Moves n tokens of a color from this player back to the bank. The caller
+    // must have checked that the player holds them.
+    pub(crate) fn remove_tokens(&mut self, color: Color, n: u8, bank: &mut [u8; 6]) {
+        let c = color as usize;
+        debug_assert!(
+            self.tokens[c] >= n,
+            "player has only {} of {:?}",
+            self.tokens[c],
+            color
+        );
+        self.tokens[c] -= n;
+        bank[c] += n;
     }
     pub fn can_acquire(&self, noble: &Noble) -> bool {
-        let power = self.purchasing_power(false);
-        noble.cost.iter().zip(power.iter()).all(|(&c, &p)| c <= p)
+        self.noble_progress(noble).iter().all(|&n| n == 0)
     }
-    pub fn acquire_best_noble(&mut self, all_nobles: &mut Vec<Noble>) -> u8 {
-        let best_noble = all_nobles
+    // Cards still needed of each color before this noble can be acquired.
+    pub fn noble_progress(&self, noble: &Noble) -> [u8; 5] {
+        let power = self.discounts();
+        let mut needed = [0; 5];
+        for (i, &c) in noble.cost.iter().enumerate() {
+            needed[i] = c.saturating_sub(power[i]);
+        }
+        needed
+    }
+    // Indices of every noble in all_nobles that this player qualifies for.
+    pub fn acquirable_nobles(&self, all_nobles: &[Noble]) -> Vec<usize> {
+        all_nobles
             .iter()
             .enumerate()
             .filter(|(_, n)| self.can_acquire(n))
-            .max_by_key(|(_, n)| n.vp);
-        if let Some((idx, n)) = best_noble {
-            let vp = n.vp;
-            self.nobles.push(all_nobles.remove(idx));
-            vp
-        } else {
-            0
+            .map(|(i, _)| i)
+            .collect()
+    }
+    // Takes the noble at idx, if the player qualifies for it, returning its VP.
+    pub fn acquire_noble(&mut self, idx: usize, all_nobles: &mut Vec<Noble>) -> Option<u8> {
+        if !self.can_acquire(all_nobles.get(idx)?) {
+            return None;
         }
+        let noble = all_nobles.remove(idx);
+        let vp = noble.vp;
+        self.nobles.push(noble);
+        Some(vp)
+    }
+    pub fn acquire_best_noble(&mut self, all_nobles: &mut Vec<Noble>) -> u8 {
+        let best_noble = self
+            .acquirable_nobles(all_nobles)
+            .into_iter()
+            .max_by_key(|&i| all_nobles[i].vp);
+        best_noble
+            .and_then(|idx| self.acquire_noble(idx, all_nobles))
+            .unwrap_or(0)
     }
     pub fn can_reserve(&self) -> bool {
-        self.reserved.len() < 3
+        self.num_reserved() < 3
+    }
+    // Reserved cards, including any hidden ones.
+    pub fn num_reserved(&self) -> usize {
+        self.reserved.len() + self.hidden_reserved.len()
     }
+    // A reserved card whose face is known. Hidden reserves aren't indexed.
     pub fn peek_reserved(&self, index: usize) -> Option<&Card> {
         self.reserved.get(index)
     }
+    pub fn hidden_reserved(&self) -> &[usize] {
+        &self.hidden_reserved
+    }
+    // Whether the reserved card at this index was drawn blind from a pile,
+    // so that only this player has seen its face.
+    pub fn is_blind_reserved(&self, index: usize) -> bool {
+        self.reserved_blind.get(index).copied().unwrap_or(false)
+    }
+    // Turns every blind reserve face down, keeping only its level, for
+    // showing this player's hand to opponents. Cards reserved from the market
+    // stay face up, since everyone saw them.
+    pub fn hide_reserved(&mut self) {
+        let reserved = std::mem::take(&mut self.reserved);
+        for (i, card) in reserved.into_iter().enumerate() {
+            if self.is_blind_reserved(i) {
+                self.hidden_reserved.push(card.level);
+            } else {
+                self.reserved.push(card);
+            }
+        }
+        self.reserved_blind = vec![false; self.reserved.len()];
+    }
+    // Swaps in a different card, e.g. for one of the worlds consistent with
+    // what an opponent can see. Returns the old one.
+    pub(crate) fn replace_reserved(&mut self, index: usize, card: Card) -> Card {
+        std::mem::replace(&mut self.reserved[index], card)
+    }
+    // Replaces the whole hand of reserved cards, without touching tokens, for
+    // setting up positions. They all count as reserved from the market.
+    pub(crate) fn set_reserved(&mut self, cards: Vec<Card>) {
+        self.reserved_blind = vec![false; cards.len()];
+        self.reserved = cards;
+        self.hidden_reserved.clear();
+    }
+    pub fn last_reserved(&self) -> Option<&Card> {
+        self.reserved.last()
+    }
     pub fn pop_reserved(&mut self, index: usize) -> Option<Card> {
         if index >= self.reserved.len() {
             return None;
         }
+        if index < self.reserved_blind.len() {
+            self.reserved_blind.remove(index);
+        }
         Some(self.reserved.remove(index))
     }
-    pub fn reserve(&mut self, card: Card, bank_gold: &mut u8) {
+    // Reserves a card, taking a gold token if one is available and the player
+    // has room for it under the token cap. Returns whether a gold token was
+    // granted.
+    pub fn reserve(&mut self, card: Card, bank_gold: &mut u8, max_tokens: u8) -> bool {
+        self.reserved_blind.resize(self.reserved.len(), false);
+        self.reserved_blind.push(false);
         self.reserved.push(card);
-        if *bank_gold > 0 && self.num_tokens() < 10 {
+        if *bank_gold > 0 && self.num_tokens() < max_tokens {
             *bank_gold -= 1;
             self.tokens[5] += 1;
+            return true;
+        }
+        false
+    }
+    // Like `reserve`, for a card drawn face down from a pile.
+    pub(crate) fn reserve_blind(&mut self, card: Card, bank_gold: &mut u8, max_tokens: u8) -> bool {
+        let granted = self.reserve(card, bank_gold, max_tokens);
+        if let Some(blind) = self.reserved_blind.last_mut() {
+            *blind = true;
         }
+        granted
     }
     pub fn buyable_reserved_cards(&self) -> Vec<usize> {
         self.reserved
@@ -118,12 +338,19 @@ impl Player {
             .map(|(i, _)| i)
             .collect()
     }
+    // What each reserved card would still cost, in reserve order.
+    pub fn reserved_affordability(&self) -> Vec<Affordability> {
+        self.reserved
+            .iter()
+            .map(|c| self.affordability(c))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::data_types::Color;
+    use crate::data_types::Ruleset;
 
     #[test]
     fn default() {
@@ -136,9 +363,25 @@ mod tests {
         assert_eq!(p.purchasing_power(false), [0, 0, 0, 0, 0]);
     }
 
+    #[test]
+    fn discounts() {
+        let mut p = Player::default();
+        p.tokens = [1, 1, 1, 1, 1, 1];
+        assert_eq!(p.discounts(), [0; 5]);
+        for (color, n) in [(Color::Blue, 2), (Color::Red, 1), (Color::Black, 3)] {
+            for _ in 0..n {
+                p.owned[color as usize].push(0);
+            }
+        }
+        assert_eq!(p.discounts(), [0, 2, 0, 1, 3]);
+        assert_eq!(p.purchasing_power(false), p.discounts());
+        assert_eq!(p.purchasing_power(true), [1, 3, 1, 2, 4]);
+    }
+
     #[test]
     fn can_buy() {
         let card = Card {
+            id: 0,
             level: 1,
             color: Color::White,
             vp: 1,
@@ -164,6 +407,86 @@ mod tests {
         assert!(p.can_buy(&card));
     }
 
+    #[test]
+    fn gold_needed() {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 1,
+            cost: [1, 0, 0, 2, 0],
+        };
+        let mut p = Player::default();
+        p.tokens = [1, 0, 0, 1, 0, 1];
+        assert_eq!(p.gold_needed(&card), Some(1));
+        p.tokens = [0, 0, 0, 0, 0, 3];
+        assert_eq!(p.gold_needed(&card), Some(3));
+        p.tokens = [1, 0, 0, 2, 0, 3];
+        assert_eq!(p.gold_needed(&card), Some(0));
+        p.tokens = [0, 0, 0, 2, 0, 0];
+        assert_eq!(p.gold_needed(&card), None);
+        // Discounts reduce the gold needed, too.
+        p.tokens = [0, 0, 0, 1, 0, 1];
+        assert_eq!(p.gold_needed(&card), None);
+        p.owned[0].push(0);
+        assert_eq!(p.gold_needed(&card), Some(1));
+    }
+
+    #[test]
+    fn missing_tokens() {
+        let card = Card {
+            id: 0,
+            level: 2,
+            color: Color::Green,
+            vp: 2,
+            cost: [0, 3, 2, 0, 4],
+        };
+        let mut p = Player::default();
+        p.tokens = [0, 3, 1, 0, 2, 0];
+        p.owned[2].push(0);
+        p.owned[4].extend([0, 0]);
+        assert_eq!(p.missing_tokens(&card), [0; 5]);
+        p.tokens = [0, 1, 0, 0, 1, 2];
+        assert_eq!(p.missing_tokens(&card), [0, 2, 1, 0, 1]);
+        // Gold doesn't count, even if it would be enough.
+        assert!(!p.can_buy(&card));
+        p.tokens[5] = 4;
+        assert!(p.can_buy(&card));
+        assert_eq!(p.missing_tokens(&card), [0, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn buy_with_payment() {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 1,
+            cost: [1, 0, 0, 2, 0],
+        };
+        let mut p = Player::default();
+        p.tokens = [1, 0, 0, 2, 0, 2];
+        let mut bank = [0; 6];
+        // Overpaying a color, paying with unheld tokens, or leaving part of
+        // the cost uncovered are all rejected.
+        assert!(!p.is_valid_payment(&card, &[1, 0, 0, 2, 0, 1]));
+        assert!(!p.is_valid_payment(&card, &[1, 1, 0, 1, 0, 1]));
+        assert!(!p.is_valid_payment(&card, &[1, 0, 0, 1, 0, 0]));
+        assert!(!p.is_valid_payment(&card, &[0, 0, 0, 0, 0, 3]));
+        assert_eq!(
+            p.clone()
+                .buy_with_payment(card.clone(), [1, 0, 0, 1, 0, 0], &mut bank),
+            Err(ActionError::InvalidPayment)
+        );
+        assert_eq!(bank, [0; 6]);
+        // Keep the blue tokens and spend both golds instead.
+        p.buy_with_payment(card, [1, 0, 0, 0, 0, 2], &mut bank)
+            .unwrap();
+        assert_eq!(p.tokens, [0, 0, 0, 2, 0, 0]);
+        assert_eq!(bank, [1, 0, 0, 0, 0, 2]);
+        assert_eq!(p.owned[0], vec![1]);
+    }
+
     #[test]
     fn reserving() {
         // Initial state.
@@ -171,32 +494,34 @@ mod tests {
         assert!(p.can_reserve());
         assert!(p.peek_reserved(0).is_none());
         assert!(p.pop_reserved(0).is_none());
-        assert_eq!(p.buyable_reserved_cards(), vec![]);
+        assert_eq!(p.buyable_reserved_cards(), Vec::<usize>::new());
 
         // Reserve a card.
         let card = Card {
+            id: 0,
             level: 1,
             color: Color::White,
             vp: 1,
             cost: [2, 2, 0, 0, 0],
         };
         let mut bank_gold = 3;
-        p.reserve(card, &mut bank_gold);
+        p.reserve(card, &mut bank_gold, Ruleset::MAX_TOKENS);
         assert_eq!(p.tokens[5], 1);
         assert_eq!(bank_gold, 2);
         assert!(p.can_reserve());
         assert_eq!(p.peek_reserved(0).unwrap().color, Color::White);
-        assert_eq!(p.buyable_reserved_cards(), vec![]);
+        assert_eq!(p.buyable_reserved_cards(), Vec::<usize>::new());
 
         // Reserve a second card, this time with no bank gold available.
         let card = Card {
+            id: 0,
             level: 1,
             color: Color::Blue,
             vp: 1,
             cost: [0, 1, 0, 0, 0],
         };
         bank_gold = 0;
-        p.reserve(card, &mut bank_gold);
+        p.reserve(card, &mut bank_gold, Ruleset::MAX_TOKENS);
         assert_eq!(p.tokens[5], 1);
         assert_eq!(bank_gold, 0);
         assert!(p.can_reserve());
@@ -205,13 +530,14 @@ mod tests {
 
         // Reserve a third card.
         let card = Card {
+            id: 0,
             level: 1,
             color: Color::Green,
             vp: 1,
             cost: [0, 0, 1, 1, 0],
         };
         bank_gold = 5;
-        p.reserve(card, &mut bank_gold);
+        p.reserve(card, &mut bank_gold, Ruleset::MAX_TOKENS);
         assert_eq!(p.tokens[5], 2);
         assert_eq!(bank_gold, 4);
         assert!(!p.can_reserve());
@@ -227,4 +553,214 @@ mod tests {
         assert!(p.peek_reserved(2).is_none());
         assert_eq!(p.buyable_reserved_cards(), vec![1]);
     }
+
+    #[test]
+    fn noble_progress() {
+        let noble = Noble {
+            id: 0,
+            vp: 3,
+            cost: [0, 3, 3, 3, 0],
+        };
+        let mut p = Player::default();
+        assert_eq!(p.noble_progress(&noble), [0, 3, 3, 3, 0]);
+        p.owned[1].push(0);
+        p.owned[2].extend([0, 0, 0, 0]);
+        assert_eq!(p.noble_progress(&noble), [0, 2, 0, 3, 0]);
+    }
+
+    #[test]
+    fn net_worth() {
+        let mut a = Player::default();
+        let mut b = Player::default();
+        assert_eq!(a.net_worth(), 0);
+        a.tokens = [1, 1, 1, 0, 0, 0];
+        b.tokens = [1, 1, 1, 0, 0, 0];
+        b.owned[3].push(0);
+        assert!(b.net_worth() > a.net_worth());
+        // VP dominates everything else.
+        a.vp_history.push((1, 1));
+        assert!(a.net_worth() > b.net_worth());
+    }
+
+    #[test]
+    fn token_primitives_conserve_supply() {
+        let mut p = Player::default();
+        let mut bank = [4, 4, 4, 4, 4, 5];
+        let supply =
+            |p: &Player, bank: &[u8; 6]| (0..6).map(|i| p.tokens[i] + bank[i]).collect::<Vec<_>>();
+        let initial = supply(&p, &bank);
+        p.add_tokens(Color::Red, 2, &mut bank);
+        p.add_tokens(Color::Gold, 1, &mut bank);
+        assert_eq!(p.tokens, [0, 0, 0, 2, 0, 1]);
+        assert_eq!(supply(&p, &bank), initial);
+        p.remove_tokens(Color::Red, 1, &mut bank);
+        assert_eq!(p.tokens, [0, 0, 0, 1, 0, 1]);
+        assert_eq!(bank, [4, 4, 4, 3, 4, 4]);
+        assert_eq!(supply(&p, &bank), initial);
+
+        // Buying also conserves the supply, including gold spent.
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 0,
+            cost: [0, 0, 0, 2, 0],
+        };
+        p.buy(card, &mut bank);
+        assert_eq!(p.tokens, [0, 0, 0, 0, 0, 0]);
+        assert_eq!(supply(&p, &bank), initial);
+    }
+
+    #[test]
+    fn discount_for() {
+        let mut p = Player::default();
+        let mut bank = [4, 4, 4, 4, 4, 5];
+        for color in [Color::Red, Color::Blue, Color::Red] {
+            let card = Card {
+                id: 0,
+                level: 1,
+                color,
+                vp: 0,
+                cost: [0, 0, 0, 0, 0],
+            };
+            p.buy(card, &mut bank);
+        }
+        assert_eq!(p.discount_for(Color::Red), 2);
+        assert_eq!(p.discount_for(Color::Blue), 1);
+        assert_eq!(p.discount_for(Color::White), 0);
+        assert_eq!(p.discount_for(Color::Gold), 0);
+        // A red discount covers a red cost without spending tokens.
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::Green,
+            vp: 0,
+            cost: [0, 1, 0, 2, 0],
+        };
+        assert!(p.can_buy(&card));
+        p.buy(card, &mut bank);
+        assert_eq!(p.tokens, [0; 6]);
+        assert_eq!(bank, [4, 4, 4, 4, 4, 5]);
+    }
+
+    #[test]
+    fn reserve_gold_grant() {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 0,
+            cost: [1, 0, 0, 0, 0],
+        };
+        let mut bank_gold = 5;
+        let mut p = Player::default();
+        p.tokens = [2, 2, 2, 2, 1, 0];
+        assert!(p.reserve(card.clone(), &mut bank_gold, Ruleset::MAX_TOKENS));
+        assert_eq!(p.num_tokens(), 10);
+        assert!(!p.reserve(card.clone(), &mut bank_gold, Ruleset::MAX_TOKENS));
+        assert_eq!(p.num_tokens(), 10);
+        assert_eq!(bank_gold, 4);
+        // No gold is granted when the bank is out, either.
+        p.tokens[0] = 0;
+        bank_gold = 0;
+        assert!(!p.reserve(card, &mut bank_gold, Ruleset::MAX_TOKENS));
+    }
+
+    #[test]
+    fn tokens_returned_by_buy() {
+        let card = Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 1,
+            cost: [1, 0, 0, 4, 0],
+        };
+        let mut p = Player::default();
+        p.tokens = [1, 3, 0, 2, 0, 3];
+        p.owned[3].push(0);
+        let predicted = p.tokens_returned_by_buy(&card);
+        // White is paid in full, red uses 2 tokens + 1 discount + 1 gold.
+        assert_eq!(predicted, [1, 0, 0, 2, 0, 1]);
+        let before = [4, 4, 4, 4, 4, 5];
+        let mut bank = before;
+        p.buy(card, &mut bank);
+        let delta = (0..6).map(|i| bank[i] - before[i]).collect::<Vec<_>>();
+        assert_eq!(delta, predicted);
+        assert_eq!(p.tokens, [0, 3, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn reserved_affordability() {
+        let mut p = Player::default();
+        let card = |cost| Card {
+            id: 0,
+            level: 1,
+            color: Color::White,
+            vp: 0,
+            cost,
+        };
+        let mut bank_gold = 1;
+        p.reserve(card([2, 0, 0, 0, 0]), &mut bank_gold, Ruleset::MAX_TOKENS);
+        p.reserve(card([0, 3, 1, 0, 0]), &mut bank_gold, Ruleset::MAX_TOKENS);
+        p.reserve(card([0, 0, 0, 0, 4]), &mut bank_gold, Ruleset::MAX_TOKENS);
+        p.tokens = [1, 1, 1, 0, 0, 1];
+        assert_eq!(
+            p.reserved_affordability(),
+            vec![
+                Affordability {
+                    missing: [1, 0, 0, 0, 0],
+                    shortfall: 0,
+                },
+                Affordability {
+                    missing: [0, 2, 0, 0, 0],
+                    shortfall: 1,
+                },
+                Affordability {
+                    missing: [0, 0, 0, 0, 4],
+                    shortfall: 3,
+                },
+            ]
+        );
+        let affordable: Vec<usize> = p
+            .reserved_affordability()
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| a.can_afford())
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(affordable, p.buyable_reserved_cards());
+    }
+
+    #[test]
+    fn acquirable_nobles() {
+        let mut p = Player::default();
+        for _ in 0..3 {
+            p.owned[2].push(0);
+            p.owned[3].push(0);
+        }
+        let mut nobles = vec![
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 3, 3, 0],
+            },
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [3, 0, 3, 0, 0],
+            },
+            Noble {
+                id: 0,
+                vp: 3,
+                cost: [0, 0, 0, 3, 0],
+            },
+        ];
+        assert_eq!(p.acquirable_nobles(&nobles), vec![0, 2]);
+        assert_eq!(p.acquire_noble(1, &mut nobles), None);
+        assert_eq!(p.acquire_noble(5, &mut nobles), None);
+        assert_eq!(p.acquire_noble(2, &mut nobles), Some(3));
+        assert_eq!(nobles.len(), 2);
+        assert_eq!(p.noble_vp(), 3);
+        assert_eq!(p.acquirable_nobles(&nobles), vec![0]);
+    }
 }
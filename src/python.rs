@@ -0,0 +1,128 @@
+// Python bindings, enabled with the "python" feature. Actions cross the
+// boundary as plain Python values mirroring their serde representation, e.g.
+// {"BuyCard": {"Market": [1, 0]}} or "Pass".
+// The pymethods macro expansion trips this lint on every PyResult method.
+#![allow(clippy::useless_conversion)]
+use crate::data_types::Action;
+use crate::game_state::GameState;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+
+#[pyclass(name = "GameState")]
+pub struct PyGameState {
+    state: GameState,
+}
+
+#[pymethods]
+impl PyGameState {
+    #[new]
+    #[pyo3(signature = (num_players, seed=None))]
+    fn new(num_players: usize, seed: Option<u64>) -> PyResult<Self> {
+        let state = match seed {
+            Some(seed) => GameState::init_seeded(num_players, seed),
+            None => GameState::init(num_players),
+        }
+        .map_err(value_error)?;
+        Ok(Self { state })
+    }
+    #[getter]
+    fn curr_player_idx(&self) -> usize {
+        self.state.curr_player_idx
+    }
+    #[getter]
+    fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+    fn winner(&self) -> Option<usize> {
+        self.state.winner()
+    }
+    fn winners(&self) -> Vec<usize> {
+        self.state.winners()
+    }
+    fn valid_actions(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let json = py.import_bound("json")?;
+        self.state
+            .valid_actions()
+            .iter()
+            .map(|a| {
+                let text = serde_json::to_string(a).map_err(value_error)?;
+                Ok(json.call_method1("loads", (text,))?.unbind())
+            })
+            .collect()
+    }
+    // Accepts an action value like those from valid_actions, or its JSON
+    // text. Returns whether the game is now over.
+    fn take_turn(&mut self, action: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let json = action.py().import_bound("json")?;
+        let text: String = json.call_method1("dumps", (action,))?.extract()?;
+        let parsed = match serde_json::from_str::<Action>(&text) {
+            Ok(parsed) => parsed,
+            Err(e) => match action.downcast::<PyString>() {
+                Ok(s) => serde_json::from_str(s.to_str()?).map_err(value_error)?,
+                Err(_) => return Err(value_error(e)),
+            },
+        };
+        Ok(self.state.take_turn(&parsed).map_err(value_error)?.is_over)
+    }
+    fn encode(&self, player_idx: usize) -> PyResult<Vec<f32>> {
+        if player_idx >= self.state.players.len() {
+            return Err(value_error("Invalid player index"));
+        }
+        Ok(self.state.encode(player_idx))
+    }
+    #[staticmethod]
+    fn encode_len() -> usize {
+        GameState::encode_len()
+    }
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.state).map_err(value_error)
+    }
+}
+
+fn value_error(e: impl ToString) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+#[pymodule]
+fn reichtum(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyGameState>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::IntoPyDict;
+
+    #[test]
+    fn self_play_from_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "reichtum").unwrap();
+            reichtum(&module).unwrap();
+            let locals = [("reichtum", module)].into_py_dict_bound(py);
+            py.run_bound(
+                r#"
+gs = reichtum.GameState(2, seed=7)
+assert len(gs.encode(0)) == reichtum.GameState.encode_len()
+assert not gs.take_turn({"TakeDifferentColorTokens": ["white", "blue", "green"]})
+assert not gs.take_turn('{"TakeSameColorTokens": "red"}')
+for _ in range(1000):
+    if gs.is_finished:
+        break
+    actions = gs.valid_actions()
+    assert actions
+    gs.take_turn(actions[0])
+try:
+    gs.take_turn("Pass")
+    assert False, "took a turn after the game ended"
+except ValueError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
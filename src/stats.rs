@@ -0,0 +1,361 @@
+use crate::data_types::Action;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Counts of the kinds of actions a single player took over a game.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayStyle {
+    // Took 3 tokens of different colors.
+    pub take_three: u32,
+    // Took 2 tokens of the same color.
+    pub take_two_same: u32,
+    // Took fewer than 3 tokens of different colors (including passing).
+    pub take_fewer: u32,
+    pub buys: u32,
+    pub reserves: u32,
+}
+
+impl PlayStyle {
+    pub fn merge(&mut self, other: &PlayStyle) {
+        self.take_three += other.take_three;
+        self.take_two_same += other.take_two_same;
+        self.take_fewer += other.take_fewer;
+        self.buys += other.buys;
+        self.reserves += other.reserves;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameStats {
+    // One entry per player, in seat order.
+    pub players: Vec<PlayStyle>,
+}
+impl GameStats {
+    pub fn new(num_players: usize) -> Self {
+        Self {
+            players: vec![PlayStyle::default(); num_players],
+        }
+    }
+    pub fn from_actions<'a>(
+        num_players: usize,
+        actions: impl IntoIterator<Item = &'a (usize, Action)>,
+    ) -> Self {
+        let mut stats = Self::new(num_players);
+        for (player_idx, action) in actions {
+            stats.record(*player_idx, action);
+        }
+        stats
+    }
+    // Adds another game's counts into this one, seat by seat.
+    pub fn merge(&mut self, other: &GameStats) {
+        if self.players.len() < other.players.len() {
+            self.players
+                .resize(other.players.len(), PlayStyle::default());
+        }
+        for (mine, theirs) in self.players.iter_mut().zip(other.players.iter()) {
+            mine.merge(theirs);
+        }
+    }
+    pub fn record(&mut self, player_idx: usize, action: &Action) {
+        let style = &mut self.players[player_idx];
+        match action {
+            Action::TakeDifferentColorTokens(colors) if colors.len() == 3 => style.take_three += 1,
+            Action::TakeDifferentColorTokens(_) | Action::Pass => style.take_fewer += 1,
+            Action::TakeSameColorTokens(_) => style.take_two_same += 1,
+            Action::BuyCard(_) | Action::BuyCardWithPayment(..) => style.buys += 1,
+            Action::ReserveCard(_) => style.reserves += 1,
+            // These finish a turn whose main action was already counted.
+            Action::ReturnTokens(_) | Action::AcquireNoble(_) => {}
+        }
+    }
+}
+
+// Summary of a single completed game, e.g. for JSON Lines output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub seed: u64,
+    pub scores: Vec<u8>,
+    pub rounds: u16,
+    pub winner: Option<usize>,
+}
+
+// Running win counts per seat, for deciding when win rates have settled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WinTally {
+    pub wins: Vec<u32>,
+    pub games: u32,
+}
+impl WinTally {
+    pub fn new(num_players: usize) -> Self {
+        Self {
+            wins: vec![0; num_players],
+            games: 0,
+        }
+    }
+    pub fn record(&mut self, winner: Option<usize>) {
+        self.games += 1;
+        if let Some(w) = winner {
+            self.wins[w] += 1;
+        }
+    }
+    // 95% Wilson score interval for a seat's win rate.
+    pub fn win_rate_interval(&self, seat: usize) -> (f64, f64) {
+        if self.games == 0 {
+            return (0.0, 1.0);
+        }
+        let z = 1.96;
+        let n = self.games as f64;
+        let p = self.wins[seat] as f64 / n;
+        let denom = 1.0 + z * z / n;
+        let center = (p + z * z / (2.0 * n)) / denom;
+        let half = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denom;
+        ((center - half).max(0.0), (center + half).min(1.0))
+    }
+    // True once every seat's win-rate interval is narrower than max_width.
+    pub fn has_converged(&self, max_width: f64) -> bool {
+        (0..self.wins.len()).all(|seat| {
+            let (lo, hi) = self.win_rate_interval(seat);
+            hi - lo < max_width
+        })
+    }
+}
+
+// Per-agent results over a batch of games.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub names: Vec<String>,
+    pub games: usize,
+    pub wins: Vec<u32>,
+    // Games where the agent shared the top score with someone else, whether
+    // or not it won the tie-break.
+    pub ties: Vec<u32>,
+    pub mean_score: Vec<f64>,
+    pub median_score: Vec<f64>,
+    pub stddev_score: Vec<f64>,
+    pub mean_rounds: f64,
+}
+impl Summary {
+    // `names` has one entry per seat, matching each record's scores.
+    pub fn new(records: &[GameRecord], names: &[String]) -> Self {
+        let n = names.len();
+        let mut wins = vec![0; n];
+        let mut ties = vec![0; n];
+        for record in records {
+            if let Some(w) = record.winner {
+                wins[w] += 1;
+            }
+            let top = record.scores.iter().max().copied().unwrap_or(0);
+            let top_seats = (0..n).filter(|&i| record.scores[i] == top);
+            if top_seats.clone().count() > 1 {
+                for i in top_seats {
+                    ties[i] += 1;
+                }
+            }
+        }
+        let mut mean_score = Vec::with_capacity(n);
+        let mut median_score = Vec::with_capacity(n);
+        let mut stddev_score = Vec::with_capacity(n);
+        for i in 0..n {
+            let mut scores: Vec<f64> = records.iter().map(|r| r.scores[i] as f64).collect();
+            scores.sort_by(f64::total_cmp);
+            let mean = mean(&scores);
+            let variance = mean_of(scores.iter().map(|s| (s - mean).powi(2)));
+            mean_score.push(mean);
+            median_score.push(median(&scores));
+            stddev_score.push(variance.sqrt());
+        }
+        Self {
+            names: names.to_vec(),
+            games: records.len(),
+            wins,
+            ties,
+            mean_score,
+            median_score,
+            stddev_score,
+            mean_rounds: mean_of(records.iter().map(|r| r.rounds as f64)),
+        }
+    }
+}
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} games, {:.1} rounds on average",
+            self.games, self.mean_rounds
+        )?;
+        writeln!(
+            f,
+            "{:<10} {:>6} {:>6} {:>6} {:>6} {:>6}",
+            "agent", "wins", "ties", "mean", "median", "stddev"
+        )?;
+        for i in 0..self.names.len() {
+            writeln!(
+                f,
+                "{:<10} {:>6} {:>6} {:>6.2} {:>6.1} {:>6.2}",
+                self.names[i],
+                self.wins[i],
+                self.ties[i],
+                self.mean_score[i],
+                self.median_score[i],
+                self.stddev_score[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    mean_of(xs.iter().copied())
+}
+fn mean_of(xs: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = xs.fold((0.0, 0), |(s, c), x| (s + x, c + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
+// Expects sorted input.
+fn median(xs: &[f64]) -> f64 {
+    match xs.len() {
+        0 => 0.0,
+        n if n % 2 == 1 => xs[n / 2],
+        n => (xs[n / 2 - 1] + xs[n / 2]) / 2.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{CardLocation, Color};
+    use crate::game_state::GameState;
+
+    #[test]
+    fn scripted_game_counts() {
+        let mut gs = GameState::init(2).unwrap();
+        let first = gs.curr_player_idx;
+        let second = 1 - first;
+        let script = [
+            Action::TakeDifferentColorTokens(vec![Color::White, Color::Blue, Color::Red]),
+            Action::TakeSameColorTokens(Color::Green),
+            Action::ReserveCard(CardLocation::Market(1, 0)),
+            Action::TakeDifferentColorTokens(vec![Color::Black]),
+            Action::ReserveCard(CardLocation::Pile(2)),
+            Action::TakeDifferentColorTokens(vec![Color::White, Color::Blue, Color::Red]),
+        ];
+        let mut stats = GameStats::new(2);
+        let mut played = Vec::new();
+        for action in script.iter() {
+            let idx = gs.curr_player_idx;
+            gs.take_turn(action).unwrap();
+            stats.record(idx, action);
+            played.push((idx, action.clone()));
+        }
+        assert_eq!(
+            stats.players[first],
+            PlayStyle {
+                take_three: 1,
+                reserves: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            stats.players[second],
+            PlayStyle {
+                take_three: 1,
+                take_two_same: 1,
+                take_fewer: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(GameStats::from_actions(2, &played), stats);
+    }
+
+    #[test]
+    fn game_record_json() {
+        let record = GameRecord {
+            seed: 42,
+            scores: vec![15, 9],
+            rounds: 27,
+            winner: Some(0),
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            json,
+            r#"{"seed":42,"scores":[15,9],"rounds":27,"winner":0}"#
+        );
+        assert_eq!(serde_json::from_str::<GameRecord>(&json).unwrap(), record);
+    }
+
+    #[test]
+    fn merge_stats() {
+        let mut total = GameStats::default();
+        let take = Action::TakeSameColorTokens(Color::Red);
+        let buy = Action::BuyCard(CardLocation::Market(1, 0));
+        total.merge(&GameStats::from_actions(
+            2,
+            &[(0, take.clone()), (1, buy.clone())],
+        ));
+        total.merge(&GameStats::from_actions(2, &[(0, take), (0, buy)]));
+        assert_eq!(
+            total.players,
+            vec![
+                PlayStyle {
+                    take_two_same: 2,
+                    buys: 1,
+                    ..Default::default()
+                },
+                PlayStyle {
+                    buys: 1,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn win_tally_convergence() {
+        let mut tally = WinTally::new(2);
+        assert!(!tally.has_converged(0.1));
+        for i in 0..20 {
+            tally.record(Some(i % 2));
+        }
+        let (lo, hi) = tally.win_rate_interval(0);
+        assert!(lo < 0.5 && 0.5 < hi);
+        assert!(!tally.has_converged(0.1));
+
+        let mut tally = WinTally::new(2);
+        for i in 0..2000 {
+            tally.record(if i % 10 == 0 { None } else { Some(i % 2) });
+        }
+        assert!(tally.has_converged(0.1));
+        assert!(!tally.has_converged(0.01));
+    }
+
+    #[test]
+    fn summary() {
+        let record = |scores: Vec<u8>, rounds, winner| GameRecord {
+            seed: 0,
+            scores,
+            rounds,
+            winner: Some(winner),
+        };
+        let records = [
+            record(vec![15, 9], 20, 0),
+            record(vec![16, 16], 24, 1),
+            record(vec![8, 17], 25, 1),
+        ];
+        let names = vec!["A".to_string(), "B".to_string()];
+        let summary = Summary::new(&records, &names);
+        assert_eq!(summary.games, 3);
+        assert_eq!(summary.wins, vec![1, 2]);
+        assert_eq!(summary.ties, vec![1, 1]);
+        assert_eq!(summary.mean_score, vec![13.0, 14.0]);
+        assert_eq!(summary.median_score, vec![15.0, 16.0]);
+        assert!((summary.stddev_score[0] - (38.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!(summary.mean_rounds, 23.0);
+        let text = summary.to_string();
+        assert!(text.starts_with("3 games, 23.0 rounds on average\n"));
+        assert!(text.contains("\nB               2      1  14.00   16.0"));
+    }
+}
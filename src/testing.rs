@@ -0,0 +1,218 @@
+use crate::data_types::{Card, Noble};
+use crate::game_state::GameState;
+
+type DynError = Box<dyn std::error::Error>;
+
+// Sets up an arbitrary position, e.g. for scenario tests. Anything not set
+// comes from a freshly dealt game. Unless the bank is set explicitly, the
+// tokens handed to players are taken out of it.
+#[derive(Debug, Clone)]
+pub struct GameStateBuilder {
+    num_players: usize,
+    seed: u64,
+    bank: Option<[u8; 6]>,
+    market: [Option<Vec<Card>>; 3],
+    nobles: Option<Vec<Noble>>,
+    players: Vec<PlayerSetup>,
+    curr_player: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PlayerSetup {
+    tokens: [u8; 6],
+    owned: Vec<Card>,
+    reserved: Vec<Card>,
+    nobles: Vec<Noble>,
+}
+
+impl GameStateBuilder {
+    pub fn new(num_players: usize) -> Self {
+        Self {
+            num_players,
+            seed: 0,
+            bank: None,
+            market: [None, None, None],
+            nobles: None,
+            players: vec![PlayerSetup::default(); num_players],
+            curr_player: 0,
+        }
+    }
+    // Seed for dealing everything that isn't set explicitly.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn bank(mut self, bank: [u8; 6]) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+    // Replaces the face-up cards at the given level (1-3).
+    pub fn market(mut self, level: usize, cards: Vec<Card>) -> Self {
+        if let Some(row) = self.market.get_mut(level.wrapping_sub(1)) {
+            *row = Some(cards);
+        }
+        self
+    }
+    pub fn nobles(mut self, nobles: Vec<Noble>) -> Self {
+        self.nobles = Some(nobles);
+        self
+    }
+    pub fn tokens(mut self, player: usize, tokens: [u8; 6]) -> Self {
+        if let Some(p) = self.players.get_mut(player) {
+            p.tokens = tokens;
+        }
+        self
+    }
+    // Cards the player has already bought, counting towards their VP.
+    pub fn owned(mut self, player: usize, cards: Vec<Card>) -> Self {
+        if let Some(p) = self.players.get_mut(player) {
+            p.owned = cards;
+        }
+        self
+    }
+    pub fn reserved(mut self, player: usize, cards: Vec<Card>) -> Self {
+        if let Some(p) = self.players.get_mut(player) {
+            p.reserved = cards;
+        }
+        self
+    }
+    // Nobles the player has already acquired, counting towards their VP.
+    pub fn player_nobles(mut self, player: usize, nobles: Vec<Noble>) -> Self {
+        if let Some(p) = self.players.get_mut(player) {
+            p.nobles = nobles;
+        }
+        self
+    }
+    // The player to move, who also counts as the first player of the round.
+    pub fn curr_player(mut self, player: usize) -> Self {
+        self.curr_player = player;
+        self
+    }
+
+    pub fn build(self) -> Result<GameState, DynError> {
+        let mut gs = GameState::init_seeded(self.num_players, self.seed)?;
+        if self.curr_player >= self.num_players {
+            return Err(format!("Invalid current player: {}", self.curr_player).into());
+        }
+        gs.start_with(self.curr_player);
+        for (level, cards) in self.market.into_iter().enumerate() {
+            let Some(cards) = cards else {
+                continue;
+            };
+            if cards.len() > 4 {
+                return Err(format!("Too many level {} market cards", level + 1).into());
+            }
+            if let Some(card) = cards.iter().find(|c| c.level != level + 1) {
+                return Err(format!(
+                    "Level {} card in the level {} market",
+                    card.level,
+                    level + 1
+                )
+                .into());
+            }
+            gs.market[level] = cards;
+        }
+        if let Some(nobles) = self.nobles {
+            gs.nobles = nobles;
+        }
+        let mut bank = gs.bank;
+        let round = gs.round();
+        for (player, setup) in gs.players.iter_mut().zip(self.players) {
+            if setup.reserved.len() > 3 {
+                return Err("A player can reserve at most 3 cards".into());
+            }
+            for (i, &n) in setup.tokens.iter().enumerate() {
+                bank[i] = bank[i]
+                    .checked_sub(n)
+                    .ok_or("Players hold more tokens than the bank has")?;
+            }
+            player.tokens = setup.tokens;
+            for card in setup.owned {
+                player.gain_card(&card);
+            }
+            player.set_reserved(setup.reserved);
+            player.nobles = setup.nobles;
+            let vp = player.card_vp() + player.noble_vp();
+            if vp > 0 {
+                player.vp_history.push((round, vp));
+            }
+        }
+        gs.bank = self.bank.unwrap_or(bank);
+        Ok(gs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{Action, CardLocation, Color};
+
+    #[test]
+    fn builder_consistency() {
+        let gs = GameStateBuilder::new(3)
+            .tokens(1, [1, 0, 0, 0, 2, 1])
+            .curr_player(2)
+            .build()
+            .unwrap();
+        assert_eq!(gs.bank, [4, 5, 5, 5, 3, 4]);
+        assert_eq!(gs.token_supply_total(), [5, 5, 5, 5, 5, 5]);
+        assert_eq!(gs.curr_player_idx, 2);
+        assert_eq!(gs.first_player(), 2);
+
+        let card = Card {
+            id: 0,
+            level: 2,
+            color: Color::Blue,
+            vp: 2,
+            cost: [0; 5],
+        };
+        let gs = GameStateBuilder::new(2)
+            .owned(0, vec![card.clone()])
+            .reserved(1, vec![card.clone()])
+            .bank([0; 6])
+            .build()
+            .unwrap();
+        assert_eq!(gs.players[0].vp(), 2);
+        assert_eq!(gs.players[0].discounts(), [0, 1, 0, 0, 0]);
+        assert_eq!(gs.players[1].peek_reserved(0), Some(&card));
+        assert_eq!(gs.bank, [0; 6]);
+
+        let build = |b: GameStateBuilder| b.build().unwrap_err().to_string();
+        assert_eq!(
+            build(GameStateBuilder::new(2).tokens(0, [5, 0, 0, 0, 0, 0])),
+            "Players hold more tokens than the bank has"
+        );
+        assert_eq!(
+            build(GameStateBuilder::new(2).market(1, vec![card])),
+            "Level 2 card in the level 1 market"
+        );
+        assert_eq!(
+            build(GameStateBuilder::new(2).curr_player(2)),
+            "Invalid current player: 2"
+        );
+    }
+
+    #[test]
+    fn builder_scenario() {
+        let card = |vp| Card {
+            id: 0,
+            level: 1,
+            color: Color::Red,
+            vp,
+            cost: [0, 0, 0, 0, 1],
+        };
+        let mut gs = GameStateBuilder::new(2)
+            .owned(0, vec![card(4), card(5), card(5)])
+            .tokens(0, [0, 0, 0, 0, 1, 0])
+            .market(1, vec![card(1), card(0), card(0), card(0)])
+            .curr_player(0)
+            .build()
+            .unwrap();
+        assert_eq!(gs.players[0].vp(), 14);
+
+        gs.take_turn(&Action::BuyCard(CardLocation::Market(1, 0)))
+            .unwrap();
+        assert_eq!(gs.players[0].vp(), 15);
+        assert!(gs.is_last_round());
+    }
+}
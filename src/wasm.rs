@@ -0,0 +1,94 @@
+// JavaScript bindings, enabled with the "wasm" feature. Games run entirely
+// client-side: agents play their seats as soon as it's their turn, and data
+// crosses the boundary as JSON (parsed into plain JS objects on the way out).
+use crate::agent::{create_agent, Agent};
+use crate::data_types::{Action, Audience};
+use crate::game_state::GameState;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WasmGame {
+    state: GameState,
+    agents: Vec<Option<Box<dyn Agent + Send>>>,
+}
+
+#[wasm_bindgen]
+impl WasmGame {
+    // Humans take the first seats, then agents of the given difficulty.
+    #[wasm_bindgen(constructor)]
+    pub fn new(num_human: usize, num_ai: usize, difficulty: usize) -> Result<WasmGame, JsError> {
+        let state = GameState::init(num_human + num_ai).map_err(js_error)?;
+        let agents = (0..num_human)
+            .map(|_| None)
+            .chain((0..num_ai).map(|_| Some(create_agent(difficulty))))
+            .collect();
+        let mut game = WasmGame { state, agents };
+        game.state
+            .fast_forward(&game.agents, &mut |_, _, _| {})
+            .map_err(js_error)?;
+        Ok(game)
+    }
+    // The game as the human to move sees it, or the spectator view once the
+    // game is over.
+    pub fn current_view(&self) -> Result<JsValue, JsError> {
+        let audience = if self.state.is_finished() {
+            Audience::Spectator
+        } else {
+            Audience::Player(self.state.curr_player_idx)
+        };
+        parse_json(&self.state.serialize_for(audience).map_err(js_error)?)
+    }
+    pub fn legal_actions(&self) -> Result<JsValue, JsError> {
+        parse_json(&serde_json::to_string(&self.state.valid_actions())?)
+    }
+    // Plays a JSON-encoded action for the human to move, then lets the
+    // agents play until it's a human's turn again.
+    pub fn apply_action(&mut self, json: &str) -> Result<(), JsError> {
+        let action: Action = serde_json::from_str(json)?;
+        self.state.take_turn(&action).map_err(js_error)?;
+        self.state
+            .fast_forward(&self.agents, &mut |_, _, _| {})
+            .map_err(js_error)?;
+        Ok(())
+    }
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+    pub fn winner(&self) -> Option<usize> {
+        self.state.winner()
+    }
+    pub fn winners(&self) -> Vec<usize> {
+        self.state.winners()
+    }
+}
+
+fn parse_json(json: &str) -> Result<JsValue, JsError> {
+    js_sys::JSON::parse(json).map_err(|_| JsError::new("Invalid JSON"))
+}
+
+fn js_error(e: impl ToString) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn full_game_against_bots() {
+        let mut game = WasmGame::new(1, 2, 1).unwrap();
+        for _turn in 1..=1000 {
+            if game.is_finished() {
+                break;
+            }
+            assert!(game.current_view().unwrap().is_object());
+            let actions = game.legal_actions().unwrap();
+            let first = js_sys::Array::from(&actions).get(0);
+            let json: String = js_sys::JSON::stringify(&first).unwrap().into();
+            game.apply_action(&json).unwrap();
+        }
+        assert!(game.is_finished());
+        assert!(game.winner().is_some());
+    }
+}